@@ -0,0 +1,138 @@
+use actix::{Actor, Handler, SyncContext};
+use cache::FilmsCache;
+use domain::{Films, EnrichmentStatus, films_key};
+use domain::cache::{GetFilms, PutFilms, CacheStats, LookupStats, CacheEntries, CacheEntry, InvalidateFilms};
+use errors::{Result, Error, ErrorKind};
+use serde_json::value::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use super::FilmsExecutor;
+
+/// Owns the films cache so its I/O, redis round trips included, never blocks
+/// `FilmsExecutor`. Started on a single thread, so a count put is the one
+/// read by the next get.
+pub struct CacheExecutor {
+    pub cache: Arc<FilmsCache>,
+    /// Age past which a cached count is reported stale.
+    pub stale_after: Duration,
+}
+
+impl Actor for CacheExecutor {
+    type Context = SyncContext<Self>;
+}
+
+impl CacheExecutor {
+    /// Cached films of a planet not expired yet.
+    fn cached(&self, name: &str) -> Result<Option<Films>> {
+        Ok(self.cache.get(name)?.and_then(|films| if films.expired() { None } else { Some(films) }))
+    }
+
+    fn entry_json(&self, name: &str, films: &Films) -> Value {
+        json!({"name": name,
+               "films": films.count,
+               "status": films.status,
+               "fetched_at": films.fetched_at,
+               "expires_at": films.expires_at,
+               "stale": films.status == EnrichmentStatus::Ok && !films.fresh(self.stale_after),
+              })
+    }
+}
+
+impl Handler<GetFilms> for CacheExecutor {
+    type Result = Result<Option<Films>>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: GetFilms, _: &mut Self::Context) -> Self::Result {
+        self.cached(&msg.0)
+    }
+}
+
+impl Handler<PutFilms> for CacheExecutor {
+    type Result = ();
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PutFilms, _: &mut Self::Context) {
+        let PutFilms(name, films) = msg;
+        // A failed refresh keeps serving the count cached before.
+        let keep = films.status != EnrichmentStatus::Ok
+            && self.cached(&name).ok().and_then(|cached| cached).map_or(false, |cached| cached.status == EnrichmentStatus::Ok);
+        if keep || films.expired() {
+            return;
+        }
+        trace!("Cached: \t{}", name);
+        if let Err(e) = self.cache.insert(name, films) {
+            warn!("got error caching films: \t {}", e);
+        }
+    }
+}
+
+impl Handler<CacheStats> for CacheExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: CacheStats, _: &mut Self::Context) -> Self::Result {
+        Ok(json!({"backend": self.cache.backend(),
+                  "size": self.cache.len()?,
+                  "evictions": self.cache.evictions()?,
+                 }))
+    }
+}
+
+impl Handler<LookupStats> for FilmsExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: LookupStats, _: &mut Self::Context) -> Self::Result {
+        Ok(json!({"hits": self.hits,
+                  "stale_hits": self.stale_hits,
+                  "misses": self.misses,
+                  "in_flight": self.in_flight.len(),
+                 }))
+    }
+}
+
+impl Handler<CacheEntries> for CacheExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: CacheEntries, _: &mut Self::Context) -> Self::Result {
+        let mut entries = self.cache.entries()?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let results : Vec<Value> = entries.iter().map(|&(ref name, ref films)| self.entry_json(name, films)).collect();
+        Ok(json!({"count": results.len(), "results": results}))
+    }
+}
+
+impl Handler<CacheEntry> for CacheExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: CacheEntry, _: &mut Self::Context) -> Self::Result {
+        let key = films_key(&msg.0);
+        match self.cached(&key)? {
+            Some(films) => Ok(self.entry_json(&key, &films)),
+            None => Err(Error::from_kind(ErrorKind::NotFound(format!("films not cached: {}", msg.0)))),
+        }
+    }
+}
+
+impl Handler<InvalidateFilms> for CacheExecutor {
+    type Result = Result<usize>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: InvalidateFilms, _: &mut Self::Context) -> Self::Result {
+        match msg.0 {
+            Some(name) => if self.cache.remove(&films_key(&name))? {
+                info!("invalidated films of {}", name);
+                Ok(1)
+            } else {
+                Err(Error::from_kind(ErrorKind::NotFound(format!("films not cached: {}", name))))
+            },
+            None => {
+                let n = self.cache.clear()?;
+                info!("invalidated {} films counts", n);
+                Ok(n)
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use actix::{Actor, Context, Handler, Recipient, Syn};
+use domain::{PlanetEvent, Subscribe, Watch};
+use futures::sync::mpsc::UnboundedSender;
+
+/// Fans out the planet events published by `WriterExecutor` to the open event
+/// streams and websocket sessions.
+#[derive(Default)]
+pub struct EventBroker {
+    subscribers: Vec<UnboundedSender<PlanetEvent>>,
+    watchers: Vec<Recipient<Syn, PlanetEvent>>,
+}
+
+impl Actor for EventBroker {
+    type Context = Context<Self>;
+}
+
+impl Handler<Subscribe> for EventBroker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) -> Self::Result {
+        self.subscribers.push(msg.0);
+        debug!("event subscribers: \t {}", self.subscribers.len());
+    }
+}
+
+impl Handler<Watch> for EventBroker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Watch, _: &mut Self::Context) -> Self::Result {
+        self.watchers.push(msg.0);
+        debug!("event watchers: \t {}", self.watchers.len());
+    }
+}
+
+impl Handler<PlanetEvent> for EventBroker {
+    type Result = ();
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PlanetEvent, _: &mut Self::Context) -> Self::Result {
+        // closed streams are dropped on the first event they miss
+        self.subscribers.retain(|s| s.unbounded_send(msg.clone()).is_ok());
+        self.watchers.retain(|w| w.do_send(msg.clone()).is_ok());
+    }
+}
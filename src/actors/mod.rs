@@ -1,30 +1,115 @@
 use pikkr::Pikkr;
-use actix::{Syn, Addr, Actor, SyncContext, Context, Handler, Arbiter, Supervised};
-use domain::{Planet, InnerPlanet, SearchResponse, ReadPlanets
-    , ReadFilms, DeletePlanet, GetPlanet, SearchPlanet};
-use lru_time_cache::LruCache;
-use std::sync::{Mutex, Arc};
+use actix::{Syn, Addr, Actor, SyncContext, Context, AsyncContext, ActorFuture, WrapFuture, Handler, Arbiter, Supervised
+    , fut, dev::Request};
+use domain::{Planet, InnerPlanet, SearchResponse, FilmsPage, Films, FilmsCount, FilmsFetched, ReadPlanets
+    , ReadFilms, DeletePlanet, GetPlanet, SearchPlanet, UpdatePlanet, PatchPlanet, Page, PlanetSort, Created
+    , GetPlanetAsOf, PlanetHistory, RestorePlanet, DeletedPlanets
+    , PlanetChanges, PlanetEventsSince, PlanetEvent, RefreshFilms, PlanetNames, EnrichmentStatus
+    , Keyset, films_key};
+use domain::resources::relation_of;
+use domain::cache::{GetFilms, PutFilms};
+use std::sync::Arc;
 use std::clone::Clone;
 use errors::{Result, Error, ErrorKind};
 use mentat::{Store,TxReport, TypedValue, entity_builder::{BuildTerms,TermBuilder}
     , QueryBuilder,Queryable, IntoResult, QueryInputs,KnownEntid, Binding};
-use serde_json::value::Value;
+use serde_json::{self, value::Value};
 use uuid::Uuid;
-use futures::{Future, Stream};
-use crossbeam_channel::{bounded,Receiver};
-use actix_web::client;
+use futures::{Future, future::{self, Either, Loop}};
+use crossbeam_channel::{bounded,Receiver,Sender};
 use actix::registry::ArbiterService;
 use std::ops::Deref;
+use std::collections::{BTreeMap, BTreeSet};
+use txlog::{self, Datom, Change};
+use upstream::{FilmsProvider, expiry, resilience::Breaker};
+use std::time::{Duration, Instant};
+use std::cmp::Ordering;
+
+pub mod resources;
+pub mod relations;
+pub mod events;
+pub mod socket;
+pub mod webhooks;
+pub mod refresher;
+pub mod cache;
+
+use self::events::EventBroker;
+
+/// Search pages followed at most before giving up on a films lookup.
+const MAX_SEARCH_PAGES: usize = 20;
+
+/// Planet of an upstream search page, only what the films count needs.
+#[derive(Deserialize)]
+struct SearchResult {
+    name: String,
+    #[serde(default)]
+    films: Vec<Value>,
+}
 
 pub struct State {
     pub db: Addr<Syn, WriterExecutor>,
-    pub processor: Addr<Syn, InboundCacheProcessor<'static>>,
     pub read: Addr<Syn, ReadExecutor>,
-    pub cache: Arc<Mutex<LruCache<String, i64>>>,
+    pub films: Addr<Syn, FilmsExecutor>,
+    pub events: Addr<Syn, EventBroker>,
+    pub webhooks: Addr<Syn, webhooks::WebhookDispatcher>,
+    pub refresher: Addr<Syn, refresher::FilmsRefresher>,
+    pub cache: Addr<Syn, cache::CacheExecutor>,
+    pub upstream: Arc<Breaker>,
+    /// Bearer token of the admin api, served to local callers only without one.
+    pub admin_token: Option<String>,
 }
 
 pub struct WriterExecutor {
     pub store: Store,
+    pub events: Addr<Syn, EventBroker>,
+}
+
+impl WriterExecutor {
+    /// Publishes the planet changes of a committed transaction, failures are
+    /// only logged as the commit already happened.
+    fn publish(&self, report: &TxReport) {
+        if let Err(e) = self.planet_events(report).map(|events| for event in events {
+            self.events.do_send(event);
+        }) {
+            warn!("could not publish tx {}: \t {}", report.tx_id, e);
+        }
+    }
+
+    /// Passes a write result through, publishing it when committed.
+    fn published(&self, res: Result<TxReport>) -> Result<TxReport> {
+        if let Ok(ref report) = res {
+            self.publish(report);
+        }
+        res
+    }
+
+    fn planet_events(&self, report: &TxReport) -> Result<Vec<PlanetEvent>> {
+        let log = txlog::datoms(&self.store, report.tx_id, report.tx_id + 1)?;
+        let mut events = Vec::new();
+        for event in planet_log_events(&self.store, &log, report.tx_id - 1)? {
+            let mut event = PlanetEvent::from(event);
+            if event.change != Change::Deleted {
+                event.name = planet_name(&self.store, event.uuid)?;
+            }
+            events.push(event);
+        }
+        Ok(events)
+    }
+}
+
+/// Planet events of a partial log after transaction `since`, planets updated
+/// in the log have their uuid asserted in earlier transactions.
+fn planet_log_events<Q: Queryable>(q: &Q, log: &[Datom], since: i64) -> Result<Vec<txlog::Event>> {
+    let mut uuids = txlog::identities(log, ":planet/uuid");
+    let mut checked = BTreeSet::new();
+    for d in log.iter() {
+        if !uuids.contains_key(&d.e) && checked.insert(d.e) {
+            if let Some(u) = planet_uuid(q, d.e)? {
+                uuids.insert(d.e, u);
+            }
+        }
+    }
+    Ok(txlog::events_of(log, &uuids, ":planet/uuid", since))
 }
 
 impl Actor for WriterExecutor {
@@ -32,25 +117,38 @@ impl Actor for WriterExecutor {
 }
 
 impl Handler<Planet> for WriterExecutor {
-    type Result = Result<TxReport>;
+    type Result = Result<Created>;
 
     #[cfg_attr(feature="flame_it", flame)]
     fn handle(&mut self, msg: Planet, _: &mut Self::Context) -> Self::Result {
-        let ip = self.store.begin_transaction()?;
-        let mut builder = ip.builder().describe_tempid("x");;
+        let report = {
+            let ip = self.store.begin_transaction()?;
+            if let Some(_) = planet_by_name(&ip, &msg.name)? {
+                return Err(Error::from_kind(ErrorKind::Conflict(format!("planet name already exists: {}", msg.name))));
+            }
+            let mut builder = ip.builder().describe_tempid("x");
 
-        let uuid = Uuid::new_v4();
-        let v_uuid = TypedValue::from(uuid);
-        let v_name = TypedValue::from(msg.name);
-        let v_climate = TypedValue::from(msg.climate);
-        let v_terrain = TypedValue::from(msg.terrain);
+            let v_uuid = TypedValue::from(Uuid::new_v4());
+            let v_name = TypedValue::from(msg.name.clone());
+            let v_climate = TypedValue::from(msg.climate);
+            let v_terrain = TypedValue::from(msg.terrain);
 
-        builder.add_kw(&kw!(:planet/uuid), v_uuid)?;
-        builder.add_kw(&kw!(:planet/name), v_name)?;
-        builder.add_kw(&kw!(:planet/climate), v_climate)?;
-        builder.add_kw(&kw!(:planet/terrain), v_terrain)?;
+            builder.add_kw(&kw!(:planet/uuid), v_uuid)?;
+            builder.add_kw(&kw!(:planet/name), v_name)?;
+            builder.add_kw(&kw!(:planet/climate), v_climate)?;
+            builder.add_kw(&kw!(:planet/terrain), v_terrain)?;
 
-        builder.commit().map_err(Into::into)
+            // checked free above, in this same transaction on the single writer thread
+            builder.commit()?
+        };
+        let uuid = match report.tempids.get("x") {
+            Some(&e) => planet_uuid(&self.store, e)?,
+            None => None,
+        };
+        let uuid = uuid.ok_or_else(|| Error::from_kind(ErrorKind::Msg(
+            format!("no planet created in tx {}", report.tx_id))))?;
+        self.publish(&report);
+        Ok(Created{report: report, uuid: uuid})
     }
 }
 
@@ -69,18 +167,38 @@ impl Handler<DeletePlanet> for WriterExecutor {
 
     #[cfg_attr(feature="flame_it", flame)]
     fn handle(&mut self, msg: DeletePlanet, _: &mut Self::Context) -> Self::Result {
+        let res = self.delete(msg.0);
+        self.published(res)
+    }
+}
+
+impl WriterExecutor {
+    fn delete(&mut self, uuid: Uuid) -> Result<TxReport> {
         let mut ip = self.store.begin_transaction()?;
+        if planet_entid(&ip, uuid)?.is_none() {
+            return Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", uuid))));
+        }
         let mut builder = TermBuilder::new();
         for (e, a, v) in ip.q_once("[:find ?e ?a ?v
                                   :in ?id
                                   :where [?e ?a ?v][?e :planet/uuid ?id]]",
-                                 QueryInputs::with_value_sequence(vec![(var!(?id), msg.0.into())]))
+                                 QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
                          .into_rel_result()?
                          .into_iter()
                          .map(eav) {
             debug!("got :\t {:?} \n {:?} \n {:?}", e, a, v);
             builder.retract(e, a, v.clone())?;
         }
+        // refs from other entities, like a resident homeworld
+        for (e, a, v) in ip.q_once("[:find ?x ?a ?e
+                                  :in ?id
+                                  :where [?e :planet/uuid ?id][?x ?a ?e]]",
+                                 QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+                         .into_rel_result()?
+                         .into_iter()
+                         .map(eav) {
+            builder.retract(e, a, v)?;
+        }
 
         let res = ip.transact_builder(builder).map_err(Into::into);
         let _ = ip.commit()?;
@@ -88,9 +206,326 @@ impl Handler<DeletePlanet> for WriterExecutor {
     }
 }    
 
+impl Handler<RestorePlanet> for WriterExecutor {
+    type Result = Result<TxReport>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: RestorePlanet, _: &mut Self::Context) -> Self::Result {
+        let res = self.restore(msg.0);
+        self.published(res)
+    }
+}
+
+impl WriterExecutor {
+    fn restore(&mut self, uuid: Uuid) -> Result<TxReport> {
+        let mut ip = self.store.begin_transaction()?;
+        if planet_entid(&ip, uuid)?.is_some() {
+            return Err(Error::from_kind(ErrorKind::Conflict(format!("planet is not deleted: {}", uuid))));
+        }
+        let e = txlog::entity(&ip, ":planet/uuid", uuid)?
+            .ok_or_else(|| Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", uuid))))?;
+        let log = txlog::of_entity(&ip, e)?;
+        let deletion = match txlog::deleted(&log, ":planet/uuid").into_iter().find(|d| d.0 == e) {
+            Some((_, tx, _)) => tx,
+            None => return Err(Error::from_kind(ErrorKind::Msg(format!("no deletion found for planet: {}", uuid)))),
+        };
+        let before = txlog::replay(&log, deletion - 1);
+        let attrs = before.get(&e)
+            .ok_or_else(|| Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", uuid))))?;
+        if let Some(&TypedValue::String(ref name)) = txlog::value(attrs, ":planet/name") {
+            if let Some(_) = planet_by_name(&ip, name)? {
+                return Err(Error::from_kind(ErrorKind::Conflict(format!("planet name already exists: {}", name))));
+            }
+        }
+
+        // refs are restored only towards entities that still exist, and not
+        // over a cardinality one link that has been set elsewhere since
+        let mut builder = TermBuilder::new();
+        for &(ref a, ref v) in attrs {
+            if let TypedValue::Ref(t) = *v {
+                let linked = match relation_of(a).and_then(|rel| rel.inverse()) {
+                    Some(inv) => taken(&ip, t, inv.attribute().as_str())?,
+                    None => false,
+                };
+                if linked || !exists(&ip, t)? {
+                    continue;
+                }
+            }
+            builder.add(KnownEntid(e), resources::attribute(&ip, a)?, v.clone())?;
+        }
+        for (x, xattrs) in before.iter().filter(|&(x, _)| *x != e) {
+            if !exists(&ip, *x)? {
+                continue;
+            }
+            for &(ref a, _) in xattrs.iter().filter(|&&(_, ref v)| *v == TypedValue::Ref(e)) {
+                if !taken(&ip, *x, a.as_str())? {
+                    builder.add(KnownEntid(*x), resources::attribute(&ip, a)?, TypedValue::Ref(e))?;
+                }
+            }
+        }
+        let res = ip.transact_builder(builder).map_err(Into::into);
+        let _ = ip.commit()?;
+        res
+    }
+}
+
+/// Whether entity `e` currently has any attribute.
+fn exists<Q: Queryable>(q: &Q, e: i64) -> Result<bool> {
+    Ok(q.q_once("[:find ?a . :in ?e :where [?e ?a _]]",
+                QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))]))
+        .into_scalar_result()?
+        .is_some())
+}
+
+/// Whether `x` holds a cardinality one relation `a`, which a restore must not overwrite.
+fn taken<Q: Queryable>(q: &Q, x: i64, a: &str) -> Result<bool> {
+    match relation_of(a) {
+        Some(rel) if !rel.many => Ok(q.q_once(&format!("[:find ?v . :in ?x :where [?x {} ?v]]", a),
+                                              QueryInputs::with_value_sequence(vec![(var!(?x), TypedValue::Ref(x))]))
+                                      .into_scalar_result()?
+                                      .is_some()),
+        _ => Ok(false),
+    }
+}
+
+fn planet_uuid<Q: Queryable>(q: &Q, e: i64) -> Result<Option<Uuid>> {
+    match q.q_once("[:find ?u .
+                     :in ?e
+                     :where [?e :planet/uuid ?u]]",
+                   QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Uuid(uuid))) => Ok(Some(uuid)),
+        _ => Ok(None),
+    }
+}
+
+fn planet_entid<Q: Queryable>(q: &Q, uuid: Uuid) -> Result<Option<KnownEntid>> {
+    match q.q_once("[:find ?e .
+                     :in ?id
+                     :where [?e :planet/uuid ?id]]",
+                   QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Ref(e))) => Ok(Some(KnownEntid(e))),
+        _ => Ok(None),
+    }
+}
+
+fn planet_uuid<Q: Queryable>(q: &Q, e: i64) -> Result<Option<Uuid>> {
+    match q.q_once("[:find ?u .
+                     :in ?e
+                     :where [?e :planet/uuid ?u]]",
+                   QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Uuid(u))) => Ok(Some(u)),
+        _ => Ok(None),
+    }
+}
+
+fn planet_name<Q: Queryable>(q: &Q, uuid: Uuid) -> Result<Option<String>> {
+    match q.q_once("[:find ?n .
+                     :in ?id
+                     :where [?e :planet/uuid ?id]
+                            [?e :planet/name ?n]]",
+                   QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::String(n))) => Ok(Some(n.as_ref().clone())),
+        _ => Ok(None),
+    }
+}
+
+fn planet_by_name<Q: Queryable>(q: &Q, name: &str) -> Result<Option<KnownEntid>> {
+    match q.q_once("[:find ?e .
+                     :in ?n
+                     :where [?e :planet/name ?n]]",
+                   QueryInputs::with_value_sequence(vec![(var!(?n), TypedValue::from(String::from(name)))]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Ref(e))) => Ok(Some(KnownEntid(e))),
+        _ => Ok(None),
+    }
+}
+
+impl WriterExecutor {
+    /// Asserts the given attributes on an existing planet in one transaction,
+    /// cardinality one attributes have their previous value retracted by Mentat.
+    fn update(&mut self, uuid: Uuid, name: Option<String>, climate: Option<String>, 
+              terrain: Option<String>) -> Result<TxReport> {
+        let ip = self.store.begin_transaction()?;
+        let e = match planet_entid(&ip, uuid)? {
+            Some(e) => e,
+            None => return Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", uuid)))),
+        };
+        if let Some(ref name) = name {
+            match planet_by_name(&ip, name)? {
+                Some(other) if other.0 != e.0 => return Err(Error::from_kind(
+                    ErrorKind::Conflict(format!("planet name already exists: {}", name)))),
+                _ => (),
+            }
+        }
+        let mut builder = ip.builder().describe(e);
+
+        if let Some(name) = name {
+            builder.add_kw(&kw!(:planet/name), TypedValue::from(name))?;
+        }
+        if let Some(climate) = climate {
+            builder.add_kw(&kw!(:planet/climate), TypedValue::from(climate))?;
+        }
+        if let Some(terrain) = terrain {
+            builder.add_kw(&kw!(:planet/terrain), TypedValue::from(terrain))?;
+        }
+
+        // the name was checked free in this transaction, and the single
+        // writer thread lets no other one commit in between
+        Ok(builder.commit()?)
+    }
+}
+
+impl Handler<UpdatePlanet> for WriterExecutor {
+    type Result = Result<TxReport>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: UpdatePlanet, _: &mut Self::Context) -> Self::Result {
+        let p = msg.1;
+        let res = self.update(msg.0, Some(p.name), Some(p.climate), Some(p.terrain));
+        self.published(res)
+    }
+}
+
+impl Handler<PatchPlanet> for WriterExecutor {
+    type Result = Result<TxReport>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PatchPlanet, _: &mut Self::Context) -> Self::Result {
+        let p = msg.1;
+        let res = self.update(msg.0, p.name, p.climate, p.terrain);
+        self.published(res)
+    }
+}
+
+/// Default soft ttl of cached films counts, a day.
+pub const DEFAULT_STALE_SECS: u64 = 86400;
+
+/// Default ttl of cached films counts, 7 days, unless upstream says
+/// otherwise with `Cache-Control: max-age`.
+pub const DEFAULT_EXPIRE_SECS: u64 = 604800;
+
+/// Default ttl of failed lookups, planets not found upstream or upstream
+/// errors, a minute.
+pub const DEFAULT_NEGATIVE_SECS: u64 = 60;
+
+/// Default timeout of a whole films lookup, a minute.
+pub const DEFAULT_LOOKUP_SECS: u64 = 60;
+
 pub struct FilmsExecutor {
+    pub provider: Option<Arc<FilmsProvider>>,
     pub processor: Option<Addr<Syn, InboundCacheProcessor<'static>>>,
-    pub cache: Option<Arc<Mutex<LruCache<String, i64>>>>
+    pub cache: Option<Addr<Syn, cache::CacheExecutor>>,
+    /// Lookups waiting on an upstream request, by planet name, so concurrent
+    /// reads of a planet share a single request.
+    pub in_flight: BTreeMap<String, Vec<Sender<FilmsCount>>>,
+    /// Age past which a cached count is served stale while refreshed.
+    pub stale_after: Duration,
+    /// Time a count is cached when upstream does not say with
+    /// `Cache-Control: max-age`.
+    pub ttl: Duration,
+    /// Time a failed lookup is cached, so reads of a missing planet or
+    /// during an upstream outage do not all go upstream.
+    pub negative_ttl: Duration,
+    /// Longest a lookup may take, every search page and retry included.
+    pub lookup_timeout: Duration,
+    /// Reads answered from the cache, fresh or stale, and from upstream.
+    pub hits: u64,
+    pub stale_hits: u64,
+    pub misses: u64,
+}
+
+impl FilmsExecutor {
+    fn source(&self) -> String {
+        self.provider.as_ref().map_or(String::new(), |p| p.source())
+    }
+
+    /// Cached films of a planet not expired yet, handed to `f` once read off
+    /// `CacheExecutor`. A failing cache is taken as a miss.
+    fn cached<F>(&mut self, name: &str, ctx: &mut Context<Self>, f: F) where
+        F: FnOnce(&mut FilmsExecutor, Option<Films>, &mut Context<Self>) + 'static
+    {
+        let cache = self.cache.clone();
+        let cache = match cache {
+            Some(cache) => cache,
+            None => return f(self, None, ctx),
+        };
+        ctx.spawn(cache.send(GetFilms(String::from(name)))
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                let films = match res {
+                    Ok(Ok(films)) => films,
+                    Ok(Err(e)) => {
+                        warn!("got error reading films cache: \t {}", e);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("got error reading films cache: \t {}", e);
+                        None
+                    }
+                };
+                f(act, films, ctx);
+                fut::ok(())
+            }));
+    }
+
+    /// Answers a read from the cached films if any, looking them up upstream
+    /// when missing or stale.
+    fn read(&mut self, key: String, cached: Option<Films>, tx: Sender<FilmsCount>, ctx: &mut Context<Self>) {
+        match cached {
+            Some(films) => {
+                debug!("Cache hit");
+                if films.status != EnrichmentStatus::Ok || films.fresh(self.stale_after) {
+                    self.hits += 1;
+                    let _ = tx.send(FilmsCount::of(films, self.source()));
+                } else {
+                    debug!("serving stale films of {}, refreshing", key);
+                    self.stale_hits += 1;
+                    let _ = tx.send(FilmsCount::stale(films, self.source()));
+                    self.lookup(&key, None, ctx);
+                }
+            }
+            None => {
+                self.misses += 1;
+                self.lookup(&key, Some(tx), ctx)
+            }
+        }
+    }
+
+    /// Looks the films of a planet up upstream, joining the lookup in flight
+    /// if any. `FilmsFetched` answers the waiters once done.
+    fn lookup(&mut self, name: &str, waiter: Option<Sender<FilmsCount>>, ctx: &mut Context<Self>) {
+        if let Some(waiters) = self.in_flight.get_mut(name) {
+            debug!("joined in-flight lookup: \t {}", name);
+            waiters.extend(waiter);
+            return;
+        }
+        let p = {self.processor.clone()};        
+        let u = {self.provider.clone()};
+        if let (Some(proc), Some(provider)) = (p, u) {
+            self.in_flight.insert(String::from(name), waiter.into_iter().collect());
+            let addr : Addr<Syn, _> = ctx.address();
+            let name_string = String::from(name);
+            let (ttl, negative_ttl) = (self.ttl, self.negative_ttl);
+            getfilms(name, provider, proc, self.lookup_timeout, move |result| {
+                debug!("got result in FilmsExecutor: \t {:?}", result);                    
+                let films = match result {
+                    Ok((count, max_age)) => Films::new(Some(count), EnrichmentStatus::Ok, max_age.unwrap_or(ttl)),
+                    Err(e) => match *e.kind() {
+                        ErrorKind::NotFound(_) => Films::new(None, EnrichmentStatus::NotFound, negative_ttl),
+                        _ => {
+                            warn!("got error: \t {}", e);
+                            Films::new(None, EnrichmentStatus::Unavailable, negative_ttl)
+                        }
+                    },
+                };
+                addr.do_send(FilmsFetched(name_string.clone(), films));
+            }); 
+        }
+    }
 }
 
 impl Actor for FilmsExecutor {
@@ -104,109 +539,119 @@ impl ArbiterService for FilmsExecutor {}
 impl Default for FilmsExecutor {
     fn default() -> FilmsExecutor {
         FilmsExecutor {
+            provider: None,
             processor: None,
             cache: None,
+            in_flight: BTreeMap::new(),
+            stale_after: Duration::from_secs(DEFAULT_STALE_SECS),
+            ttl: Duration::from_secs(DEFAULT_EXPIRE_SECS),
+            negative_ttl: Duration::from_secs(DEFAULT_NEGATIVE_SECS),
+            lookup_timeout: Duration::from_secs(DEFAULT_LOOKUP_SECS),
+            hits: 0,
+            stale_hits: 0,
+            misses: 0,
         }
     }
 }
 
 impl Handler<ReadFilms> for FilmsExecutor {
-    type Result = Result<Receiver<Result<i64>>>;
+    type Result = Result<Receiver<FilmsCount>>;
 
     #[cfg_attr(feature="flame_it", flame)]
-    fn handle(&mut self, msg: ReadFilms, _: &mut Self::Context) -> Self::Result {
-        let c = {self.cache.clone()};
-        let p = {self.processor.clone()};        
-        let (tx, rx) = bounded::<Result<i64>>(1);
-        let s = tx.clone(); 
-        if let Some(cache) = c {
-            if let Some(proc) = p {
-                getfilms(&msg.0, proc, cache, move |result| {
-                    debug!("got result in FilmsExecutor: \t {:?}", result);                    
-                    match s.send(result) {
-                        Ok(_) => trace!("result sent in FilmsExecutor"),
-                        Err(e) => warn!("got error: 4\t {}", e),
-                    }
-                }); 
-            }
-        }
- 
+    fn handle(&mut self, msg: ReadFilms, ctx: &mut Self::Context) -> Self::Result {
+        let (tx, rx) = bounded::<FilmsCount>(1);
+        let key = films_key(&msg.0);
+        let name = key.clone();
+        self.cached(&name, ctx, move |act, cached, ctx| act.read(key, cached, tx, ctx));
+
         debug!("return rx in FilmsExecutor");  
         Ok(rx)
     }
 }
 
-#[cfg_attr(feature="flame_it", flame)]
-pub fn getfilms<F> (name: &str, processor : Addr<Syn, InboundCacheProcessor<'static>>, 
-                cache : Arc<Mutex<LruCache<String, i64>>>, f: F) where
-                F : Fn(Result<i64>) + 'static
-{
-        {
-            match cache.lock() {
-                Ok(mut guard) => {
-                    trace!("cache len: {}", guard.len());
-                    if let Some(v) = guard.get(name) {
-                        debug!("Cache hit");
-                        f(Ok((*v).to_owned()));
-                        return;
-                    }
-                }                    
-                Err(poisoned) => {
-                    f(Err(Error::from_kind(
-                            ErrorKind::Poisoned(
-                                String::from(format!("{}", poisoned))
-                            )
-                        )
-                    )); 
-                    return;
-                }
-            }                
+impl Handler<RefreshFilms> for FilmsExecutor {
+    type Result = ();
+
+    fn handle(&mut self, msg: RefreshFilms, ctx: &mut Self::Context) {
+        let key = films_key(&msg.0);
+        let name = key.clone();
+        self.cached(&name, ctx, move |act, cached, ctx| {
+            let fresh = cached
+                .map_or(false, |films| films.status != EnrichmentStatus::Ok || films.fresh(act.stale_after));
+            if !fresh {
+                trace!("refreshing films of {}", key);
+                act.lookup(&key, None, ctx);
+            }
+        });
+    }
+}
+
+impl Handler<FilmsFetched> for FilmsExecutor {
+    type Result = ();
+
+    fn handle(&mut self, msg: FilmsFetched, _: &mut Self::Context) {
+        let FilmsFetched(name, films) = msg;
+        if let Some(ref cache) = self.cache {
+            cache.do_send(PutFilms(name.clone(), films.clone()));
+        }
+        let waiters = self.in_flight.remove(&name).unwrap_or_default();
+        trace!("answering {} lookups of {}", waiters.len(), name);
+        let count = FilmsCount::of(films, self.source());
+        for tx in waiters {
+            match tx.send(count.clone()) {
+                Ok(_) => trace!("result sent in FilmsExecutor"),
+                Err(e) => warn!("got error: 4\t {}", e),
+            }
         }
+    }
+}
 
-        let farc = Arc::new(f);
-        let farc1 = farc.clone();
-        let farc2 = farc.clone();
-        let farc3 = farc.clone();
-        let farc4 = farc.clone();
+#[cfg_attr(feature="flame_it", flame)]
+pub fn getfilms<F> (name: &str, provider: Arc<FilmsProvider>, processor : Addr<Syn, InboundCacheProcessor<'static>>, 
+                timeout: Duration, f: F) where
+                F : Fn(Result<(i64, Option<Duration>)>) + 'static
+{
         let name_string = String::from(name);
-        let fut = {
-            let url = format!("https://swapi.co/api/planets/?search={}",name);
-            client::get(url)   // <- Create request builder
-                .header("User-Agent", "Actix-web")
-                .finish().unwrap()
-                .send()                               // <- Send http request
-                .map_err(move |e| {
-                    warn!("got error 1: \t {}", e);
-                    &farc1(Err(Error::from_kind(ErrorKind::Failure(::failure::Error::from(e).compat()))));
-                })
-                .and_then(|response|                 // <- server http response
-                    response.concat2()     // <- get Body future
-                            .map_err(move |e| {
-                                warn!("got error 2: \t {}", e);
-                                &farc2(Err(Error::from_kind(ErrorKind::Failure(::failure::Error::from(e).compat()))));
-                            })
-                            .and_then(move |body| {  // <- complete body                              
-                                processor.send(SearchResponse(name_string,body.to_vec()))
-                                    .map_err(move |e| {
-                                        warn!("got error 3: \t {}", e);
-                                        &farc3(Err(Error::from_kind(ErrorKind::Failure(::failure::Error::from(e).compat()))));
-                                    })
-                                    .and_then(move |qtdres| {
-                                        debug!("got qtdres \t {:?}", qtdres);
-                                        &farc4(qtdres);
-                                        Ok(())
-                                    })
-                            })
-                )
-
-        };
+        let pages = provider.clone();
+        let fut = provider.search(name)
+                .and_then(move |response| future::loop_fn((response, None, 1), move |(response, max_age, page)| {
+                    let pages = pages.clone();
+                    // A result is cached no longer than any page it was read from.
+                    let max_age = match (max_age, response.max_age) {
+                        (Some(a), Some(b)) => Some(::std::cmp::min(a, b)),
+                        (a, b) => a.or(b),
+                    };
+                    processor.send(SearchResponse(name_string.clone(), response.body))
+                        .map_err(|e| {
+                            warn!("got error 3: \t {}", e);
+                            Error::from_kind(ErrorKind::Failure(::failure::Error::from(e).compat()))
+                        })
+                        .and_then(|res| res)
+                        .and_then(move |found| match found {
+                            FilmsPage::Found(count) => Either::A(future::ok(Loop::Break((count, max_age)))),
+                            FilmsPage::Next(_) if page >= MAX_SEARCH_PAGES => Either::A(future::err(Error::from_kind(
+                                ErrorKind::Upstream(format!("search not resolved in {} pages", page))))),
+                            FilmsPage::Next(url) => {
+                                debug!("following page {}: \t {}", page + 1, url);
+                                Either::B(pages.next(&url).map(move |response| Loop::Continue((response, max_age, page + 1))))
+                            }
+                        })
+                }))
+                // the whole lookup, every page and retry, is bounded
+                .select(expiry(timeout, format!("films lookup not done in {}s", timeout.as_secs())))
+                .map(|(found, _)| found)
+                .map_err(|(e, _)| e)
+                .then(move |qtdres| {
+                    debug!("got qtdres \t {:?}", qtdres);
+                    f(qtdres);
+                    Ok::<(), ()>(())
+                });
 
-        Arbiter::handle().spawn(fut.map_err(|_|()).map(|_|()));
+        Arbiter::handle().spawn(fut);
 }
 
 pub struct InboundCacheProcessor <'a> {
     pub pikkr: Pikkr<'a>, 
-    pub cache: Arc<Mutex<LruCache<String, i64>>>,       
 }
 
 impl Actor for InboundCacheProcessor<'static> {
@@ -214,113 +659,196 @@ impl Actor for InboundCacheProcessor<'static> {
 }
 
 impl<'a> Handler<SearchResponse> for InboundCacheProcessor<'static> {
-    type Result = Result<i64>;
+    type Result = Result<FilmsPage>;
 
     #[cfg_attr(feature="flame_it", flame)]
     fn handle(&mut self, msg: SearchResponse, _: &mut Self::Context) -> Self::Result {
-        let cache = {self.cache.clone()};
-        {
-            match cache.lock() {
-                Ok(mut guard) => if let Some(v) = guard.get(&msg.0) {
-                    debug!("Cache hit");
-                    return Ok((*v).to_owned());
-                }                    
-                Err(poisoned) => {
-                    return Err(Error::from_kind(
-                                ErrorKind::Poisoned(
-                                    String::from(format!("{}", poisoned))
-                                ))
-                           ); 
-                }
-            }                
+        let res = self.pikkr.parse(&msg.1)?;
+        if res.len() != 2 {
+            return Err(Error::from_kind(
+                ErrorKind::Msg(String::from(format!("unexpected data to parse, len: \t{}",res.len())))
+            ));
         }
+        let results : Vec<SearchResult> = match res[1] {
+            Some(results) => serde_json::from_slice(results)?,
+            None => return Err(Error::from_kind(ErrorKind::Msg(String::from(
+                "corrupt data!")))),
+        };
 
-        // info!("Got: \n{}", String::from_utf8(msg.1.clone())?);
-
-        let res = self.pikkr.parse(&msg.1)?;
-            if res.len() == 2 {
-                if let Some(count) = res[0] {
-                    let c_str = ::std::str::from_utf8(count)?;
-                    let c = c_str.parse::<i64>()?;
-                    let qtd = { 
-                        if c == 1 {
-                            if let Some(qtdvec) = res[1] {
-                                let qtd_str = ::std::str::from_utf8(qtdvec)?;
-                                if qtd_str.len() > 3 {
-                                    let q : Vec<&str> = qtd_str.split(',').collect();
-                                    q.len() as i64
-                                } else {
-                                    0
-                                }
-                            } else {
-                                return Err(Error::from_kind(ErrorKind::Msg(String::from(
-                                    "corrupt data!"))));
-                            }
-                        } else {
-                            0
-                        }
-                    };
+        let next : Option<String> = match res[0] {
+            Some(next) => serde_json::from_slice(next)?,
+            None => None,
+        };
+        search_page(&msg.0, &results, next)
+    }
+}
 
-                    match cache.lock() {
-                        Ok(mut guard) => {
-                            trace!("Cached: \t{}", msg.0);                            
-                            let _ = guard.insert(msg.0, qtd);
-                            trace!("cache len: {}", guard.len());
-                            return Ok(qtd);
-                        }  
-                        Err(poisoned) => {
-                            return Err(Error::from_kind(
-                                                        ErrorKind::Poisoned(
-                                                            String::from(format!("{}", poisoned))
-                                                        )
-                                                    )
-                            ); 
-                        }                         
-                    }
-                } else {
-                    return Err(Error::from_kind(
-                        ErrorKind::Msg(String::from(format!("unexpected data to parse, res[0] \t{:?}",res[0])))
-                    ));                    
-                }
-            } else {
-                return Err(Error::from_kind(
-                    ErrorKind::Msg(String::from(format!("unexpected data to parse, len: \t{}",res.len())))
-                ));
-            }
+/// Films of the planet named `name` in a search page, or the page to look
+/// next. SWAPI searches by substring, only the planet named exactly as
+/// searched counts, compared by films key.
+fn search_page(name: &str, results: &[SearchResult], next: Option<String>) -> Result<FilmsPage> {
+    let key = films_key(name);
+    match results.iter().find(|p| films_key(&p.name) == key) {
+        Some(planet) => Ok(FilmsPage::Found(planet.films.len() as i64)),
+        None => match next {
+            Some(url) => Ok(FilmsPage::Next(url)),
+            None => Err(Error::from_kind(ErrorKind::NotFound(
+                format!("planet not found upstream: {}", name)))),
+        },
     }
 }
 
 pub struct ReadExecutor {
     pub store: Store,
     pub films: Addr<Syn, FilmsExecutor>,
+    /// Longest a read waits for a films count before reporting it unavailable.
+    pub films_wait: Duration,
 }
 
 impl Actor for ReadExecutor {
     type Context = SyncContext<Self>;
 }
 
+/// Waits until `deadline` for a films lookup sent to `FilmsExecutor`,
+/// unavailable when it could not be answered in time.
+fn wait_films(req: Request<Syn, FilmsExecutor, ReadFilms>, deadline: Instant) -> FilmsCount {
+    debug!("waiting for future in ReadExecutor");
+    let now = Instant::now();
+    let wait = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+    match req.wait() {
+        Ok(Ok(rx)) => match rx.recv_timeout(wait) {
+            Ok(films) => films,
+            Err(e) => {
+                warn!("got error: \t {}", e);
+                FilmsCount::unavailable(None)
+            }
+        },
+        Ok(Err(e)) => {
+            warn!("got error: \t {}", e);
+            FilmsCount::unavailable(None)
+        }
+        Err(e) => {
+            warn!("got error 5: \t {}", e);
+            FilmsCount::unavailable(None)
+        }
+    }
+}
+
+type PlanetRow = (String, Arc<String>, Arc<String>, Arc<String>);
+
+fn text(attrs: &[(String, TypedValue)], a: &str) -> Arc<String> {
+    match txlog::value(attrs, a) {
+        Some(&TypedValue::String(ref s)) => s.clone(),
+        _ => Arc::new(String::from("")),
+    }
+}
+
+fn planet_row(attrs: &[(String, TypedValue)]) -> Option<PlanetRow> {
+    match txlog::value(attrs, ":planet/uuid") {
+        Some(&TypedValue::Uuid(ref u)) => Some((u.hyphenated().to_string(), text(attrs, ":planet/name"),
+                                                text(attrs, ":planet/climate"), text(attrs, ":planet/terrain"))),
+        _ => None,
+    }
+}
+
+/// Planets replayed from the transaction log as of transaction `tx`.
+fn planets_as_of(log: &[Datom], tx: i64) -> BTreeMap<i64, PlanetRow> {
+    txlog::replay(log, tx).into_iter()
+        .filter_map(|(e, attrs)| planet_row(&attrs).map(|row| (e, row)))
+        .collect()
+}
+
+/// Rows of one page of `rows`, already in `sort` order, and the keysets of the pages
+/// around it. A keyset positions the page, otherwise it starts at `offset`.
+/// Mentat query predicates compare numbers and instants only, not the string
+/// keys, so pages are cut here from every row rather than in the query.
+fn window<T, F>(rows: Vec<T>, sort: PlanetSort, keyset: Option<&Keyset>, offset: usize, limit: usize, key: F)
+    -> (Vec<T>, Option<Keyset>, Option<Keyset>) where F: Fn(&T) -> Keyset {
+    let (start, end) = match keyset {
+        Some(at) if at.before => {
+            let end = rows.iter().position(|r| sort.compare(&key(r), at) != Ordering::Less).unwrap_or(rows.len());
+            (end.saturating_sub(limit), end)
+        }
+        Some(at) => {
+            let start = rows.iter().position(|r| sort.compare(&key(r), at) == Ordering::Greater).unwrap_or(rows.len());
+            (start, ::std::cmp::min(start + limit, rows.len()))
+        }
+        None => {
+            let start = ::std::cmp::min(offset, rows.len());
+            (start, ::std::cmp::min(start + limit, rows.len()))
+        }
+    };
+    let previous = if start > 0 && start < rows.len() {
+        Some(Keyset{before: true, ..key(&rows[start])})
+    } else {
+        None
+    };
+    let next = if end < rows.len() && end > start { Some(key(&rows[end - 1])) } else { None };
+    let page = rows.into_iter().skip(start).take(end - start).collect();
+    (page, next, previous)
+}
+
 impl Handler<ReadPlanets> for ReadExecutor {
-    type Result = Result<Value>;
+    type Result = Result<Page>;
 
     #[cfg_attr(feature="flame_it", flame)]
-    fn handle(&mut self, _: ReadPlanets, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, page: ReadPlanets, _: &mut Self::Context) -> Self::Result {
         let ref mut store = self.store;
         let films = {self.films.clone()};
-        let mut res1 : Vec<InnerPlanet> = 
-            QueryBuilder::new(store, r#"[:find ?u, ?n, ?c, ?t  
-                                         :where [?x :planet/uuid ?u]
-                                                [?x :planet/name ?n]
-                                                [?x :planet/climate ?c]
-                                                [?x :planet/terrain ?t]
-                                        ]"#)
-                .execute_rel()?
-                .into_iter()
-                .map(|row| {
-                    debug!("retrieving database");
-                    let uuid = row.get(0).map_or(String::from(""), |t| t.to_owned().into_uuid_string().expect("uuid"));
-                    let name = row.get(1).map_or(Arc::new(String::from("")), |t| t.to_owned().into_string().expect("name"));
-                    let climate = row.get(2).map_or(Arc::new(String::from("")), |t| t.to_owned().into_string().expect("climate"));
-                    let terrain = row.get(3).map_or(Arc::new(String::from("")), |t| t.to_owned().into_string().expect("terrain"));
+        let deadline = Instant::now() + self.films_wait;
+        let sort = page.sort;
+        let (rows, as_of) : (Vec<PlanetRow>, Option<i64>) = match page.as_of {
+            Some(as_of) => {
+                let tx = txlog::resolve(&*store, as_of)?;
+                let log = txlog::datoms(&*store, txlog::TX0, tx.saturating_add(1))?;
+                let mut rows : Vec<PlanetRow> = planets_as_of(&log, tx).into_iter().map(|(_, row)| row).collect();
+                rows.sort_by(|a, b| sort.compare(&sort.keyset(&a.0, &a.1, &a.2), &sort.keyset(&b.0, &b.1, &b.2)));
+                (rows, Some(tx))
+            }
+            None => {
+                // the store orders the rows, names are unique so they settle ties;
+                // the page itself is cut by `window`, see there why
+                let order = match sort {
+                    PlanetSort::Name => "(asc ?n)",
+                    PlanetSort::NameDesc => "(desc ?n)",
+                    PlanetSort::Climate => "(asc ?c) (asc ?n)",
+                };
+                let query = format!(r#"[:find ?u, ?n, ?c, ?t
+                                        :where [?x :planet/uuid ?u]
+                                               [?x :planet/name ?n]
+                                               [?x :planet/climate ?c]
+                                               [?x :planet/terrain ?t]
+                                        :order {}]"#, order);
+                (QueryBuilder::new(store, query)
+                    .execute_rel()?
+                    .into_iter()
+                    .map(|row| {
+                        debug!("retrieving database");
+                        let uuid = row.get(0).map_or(String::from(""), |t| t.to_owned().into_uuid_string().expect("uuid"));
+                        let name = row.get(1).map_or(Arc::new(String::from("")), |t| t.to_owned().into_string().expect("name"));
+                        let climate = row.get(2).map_or(Arc::new(String::from("")), |t| t.to_owned().into_string().expect("climate"));
+                        let terrain = row.get(3).map_or(Arc::new(String::from("")), |t| t.to_owned().into_string().expect("terrain"));
+                        (uuid, name, climate, terrain)
+                    })
+                    .collect(), None)
+            }
+        };
+
+        let count = rows.len();
+        let (rows, next, previous) = window(rows, sort, page.keyset.as_ref(), page.offset, page.limit,
+                                            |r| sort.keyset(&r.0, &r.1, &r.2));
+        if as_of.is_some() {
+            let results = rows.into_iter().map(|(uuid, name, climate, terrain)| json!({"uuid": uuid,
+                                                                           "name": name,
+                                                                           "climate": climate,
+                                                                           "terrain": terrain
+                                                                          }))
+                              .collect();
+            return Ok(Page{count: count, results: results, next: next, previous: previous, as_of: as_of});
+        }
+
+        let mut res1 : Vec<InnerPlanet> = rows.into_iter()
+                .map(|(uuid, name, climate, terrain)| {
                     let n = name.as_ref().clone();
                     trace!("sending ReadFilms req");
                     let req = films.send(ReadFilms(n)); // will parallel send before waiting
@@ -328,61 +856,202 @@ impl Handler<ReadPlanets> for ReadExecutor {
                     InnerPlanet{uuid: uuid, name: name, climate: climate, terrain: terrain, request: Some(req)}
                 })
                 .collect();
-        let res2 : Vec<Value> = res1.iter_mut()
+        let results : Vec<Value> = res1.iter_mut()
                 .map(|t| {
-                    let (tx, rx) = bounded::<i64>(1);
-                    debug!("waiting for future in ReadExecutor");
-                    // let r = ::std::mem::replace(&mut t.request, None);
-                    let _ = t.request.take().unwrap().wait()
-                        .map_err(|e| {
-                            warn!("got error 5: \t {}", e);
-                        })
-                        .and_then(|r| {
-                            debug!("got r on ReadExecutor: \t {:?}", r);
-                            let qtd = match r {
-                                Ok(rx) => match rx.recv() {
-                                    Ok(rst) => match rst {
-                                        Ok(v) => v,
-                                        Err(e) => {
-                                            warn!("got error: \t {}", e);
-                                            -1i64
-                                        },
-                                    }
-                                Err(e) => {
-                                    warn!("got error: \t {}", e);
-                                    -1i64
-                                }},
-                                Err(e) => {
-                                    warn!("got error: \t {}", e);
-                                    -1i64
-                                }
-                            };
-                            match tx.send(qtd) {
-                                Ok(_) => trace!("qtd sent on ReadExecutor"),
-                                Err(e) => warn!("got error 6: \t {}", e),
-                            }
-                            Ok(())
-                        });
-                    debug!("waiting for rx in ReadExecutor");
-                    let qtd = match rx.recv() {
-                        Ok(qtd) => qtd,
-                        Err(e) => {
-                            warn!("got error 7: \t {}", e);                            
-                            -1
-                        }
-                    };
+                    let qtd = wait_films(t.request.take().unwrap(), deadline);
 
                     json!({"uuid": t.uuid,
                            "name": t.name,
                            "climate": t.climate,
                            "terrain": t.terrain,
-                           "films": qtd
+                           "films": qtd.count,
+                           "enrichment": qtd.enrichment
                           })
-                    
                 })
                 .collect();
 
-        Ok(json!(res2))
+        Ok(Page{count: count, results: results, next: next, previous: previous, as_of: None})
+    }
+}
+
+impl Handler<GetPlanetAsOf> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: GetPlanetAsOf, _: &mut Self::Context) -> Self::Result {
+        let tx = txlog::resolve(&self.store, msg.1)?;
+        let e = match txlog::entity(&self.store, ":planet/uuid", msg.0)? {
+            Some(e) => e,
+            None => return Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", msg.0)))),
+        };
+        let log = txlog::of_entity(&self.store, e)?;
+        match planets_as_of(&log, tx).remove(&e) {
+            Some((uuid, name, climate, terrain)) => Ok(json!({"uuid": uuid,
+                                                              "name": name,
+                                                              "climate": climate,
+                                                              "terrain": terrain,
+                                                              "as_of": tx
+                                                             })),
+            None => Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {} as of {}", msg.0, tx)))),
+        }
+    }
+}
+
+/// Json of a logged value, refs are reported by the uuid of the entity they point to.
+fn logged_json(uuids: &BTreeMap<i64, Uuid>, v: &TypedValue) -> Value {
+    match *v {
+        TypedValue::Ref(r) => uuids.get(&r).map_or(json!(r), |u| json!(u.hyphenated().to_string())),
+        ref v => resources::typed_json(v.clone()),
+    }
+}
+
+/// Changes of entity `e` grouped by transaction, pairing the values retracted
+/// and asserted on each attribute. Refs from other entities are reported with
+/// the uuid of the referencing `entity`.
+fn history(log: &[Datom], uuids: &BTreeMap<i64, Uuid>, e: i64) -> Vec<Value> {
+    let mut txs : BTreeMap<i64, Vec<&Datom>> = BTreeMap::new();
+    for d in log.iter().filter(|d| d.e == e || d.v == TypedValue::Ref(e)) {
+        txs.entry(d.tx).or_insert_with(Vec::new).push(d);
+    }
+    txs.into_iter()
+        .map(|(tx, datoms)| {
+            let instant = datoms[0].instant;
+            let mut attrs : BTreeMap<(i64, &str), (Vec<&TypedValue>, Vec<&TypedValue>)> = BTreeMap::new();
+            for &d in datoms.iter() {
+                let values = attrs.entry((d.e, d.a.as_str())).or_insert_with(|| (Vec::new(), Vec::new()));
+                if d.added { values.1.push(&d.v) } else { values.0.push(&d.v) }
+            }
+            let mut changes = Vec::new();
+            for ((x, a), (old, new)) in attrs {
+                for i in 0..::std::cmp::max(old.len(), new.len()) {
+                    let mut change = json!({
+                        "attribute": a,
+                        "old": old.get(i).map_or(Value::Null, |v| logged_json(uuids, v)),
+                        "new": new.get(i).map_or(Value::Null, |v| logged_json(uuids, v)),
+                    });
+                    if x != e {
+                        change["entity"] = logged_json(uuids, &TypedValue::Ref(x));
+                    }
+                    changes.push(change);
+                }
+            }
+            json!({"tx_id": tx, "tx_instant": instant, "changes": changes})
+        })
+        .collect()
+}
+
+impl Handler<PlanetHistory> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PlanetHistory, _: &mut Self::Context) -> Self::Result {
+        match txlog::entity(&self.store, ":planet/uuid", msg.0)? {
+            Some(e) => {
+                let log = txlog::of_entity(&self.store, e)?;
+                // uuids of the entities linked from or to the planet
+                let mut uuids = BTreeMap::new();
+                for d in log.iter() {
+                    for x in [Some(d.e), match d.v { TypedValue::Ref(r) => Some(r), _ => None }].iter().filter_map(|x| *x) {
+                        if !uuids.contains_key(&x) {
+                            if let Some(u) = txlog::uuid_of(&self.store, x)? {
+                                uuids.insert(x, u);
+                            }
+                        }
+                    }
+                }
+                let transactions = history(&log, &uuids, e);
+                Ok(json!({"uuid": msg.0, "count": transactions.len(), "transactions": transactions}))
+            }
+            None => Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", msg.0)))),
+        }
+    }
+}
+
+impl Handler<PlanetNames> for ReadExecutor {
+    type Result = Result<Vec<String>>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: PlanetNames, _: &mut Self::Context) -> Self::Result {
+        Ok(self.store.q_once("[:find [?n ...] :where [?x :planet/name ?n]]", None)
+            .into_coll_result()?
+            .into_iter()
+            .filter_map(|b| match b {
+                Binding::Scalar(TypedValue::String(n)) => Some(n.as_ref().clone()),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+impl Handler<DeletedPlanets> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: DeletedPlanets, _: &mut Self::Context) -> Self::Result {
+        let deleted = txlog::deleted(&txlog::of_attribute(&self.store, ":planet/uuid")?, ":planet/uuid");
+        // only the planets of the page are replayed, all of them at once
+        let mut log = Vec::new();
+        for &(e, _, _) in deleted.iter().take(msg.0) {
+            log.extend(txlog::of_entity(&self.store, e)?);
+        }
+        // refs between two planets of the page are logged for both
+        log.sort_by(|a, b| (a.tx, a.added, a.e, &a.a).cmp(&(b.tx, b.added, b.e, &b.a)));
+        log.dedup_by(|a, b| a.tx == b.tx && a.added == b.added && a.e == b.e && a.a == b.a && a.v == b.v);
+        let states = txlog::deleted_states(&log, ":planet/uuid");
+        let results : Vec<Value> = deleted.iter()
+            .take(msg.0)
+            .filter_map(|&(e, tx, instant)| states.get(&e).and_then(|attrs| planet_row(attrs)).map(|(uuid, name, climate, terrain)| {
+                json!({"uuid": uuid,
+                       "name": name,
+                       "climate": climate,
+                       "terrain": terrain,
+                       "deleted_tx": tx,
+                       "deleted_at": instant
+                      })
+            }))
+            .collect();
+        Ok(json!({"count": deleted.len(), "results": results}))
+    }
+}
+
+impl Handler<PlanetChanges> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PlanetChanges, _: &mut Self::Context) -> Self::Result {
+        let log = txlog::since(&self.store, msg.0)?;
+        // first and last change of every planet touched since the watermark
+        let mut planets : BTreeMap<i64, (Uuid, Change, Change)> = BTreeMap::new();
+        for event in planet_log_events(&self.store, &log, msg.0)? {
+            planets.entry(event.e)
+                .or_insert((event.uuid, event.change, event.change))
+                .2 = event.change;
+        }
+        let (mut created, mut updated, mut deleted) = (Vec::new(), Vec::new(), Vec::new());
+        for (_, (uuid, first, last)) in planets {
+            let uuid = uuid.hyphenated().to_string();
+            match (first != Change::Created, last != Change::Deleted) {
+                (false, true) => created.push(uuid),
+                (true, true) => updated.push(uuid),
+                (true, false) => deleted.push(uuid),
+                (false, false) => (), // created and deleted since, never seen by the consumer
+            }
+        }
+        Ok(json!({"created": created,
+                  "updated": updated,
+                  "deleted": deleted,
+                  "watermark": txlog::latest(&log).map_or(msg.0, |tx| ::std::cmp::max(tx, msg.0))
+                 }))
+    }
+}
+
+impl Handler<PlanetEventsSince> for ReadExecutor {
+    type Result = Result<Vec<PlanetEvent>>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PlanetEventsSince, _: &mut Self::Context) -> Self::Result {
+        let log = txlog::since(&self.store, msg.0)?;
+        Ok(planet_log_events(&self.store, &log, msg.0)?.into_iter().map(PlanetEvent::from).collect())
     }
 }
 
@@ -393,6 +1062,7 @@ impl Handler<SearchPlanet> for ReadExecutor {
     fn handle(&mut self, search : SearchPlanet, _: &mut Self::Context) -> Self::Result {
         let ref mut store = self.store;
         let films = {self.films.clone()};
+        let deadline = Instant::now() + self.films_wait;
         let s = format!("*{}*", search.0);
         let mut res1 : Vec<InnerPlanet> = 
             QueryBuilder::new(store, "[:find ?id ?n, ?c, ?t
@@ -420,53 +1090,14 @@ impl Handler<SearchPlanet> for ReadExecutor {
                 .collect();
         let res2 : Vec<Value> = res1.iter_mut()
                 .map(|t| {
-                    let (tx, rx) = bounded::<i64>(1);
-                    debug!("waiting for future in ReadExecutor");
-                    // let r = ::std::mem::replace(&mut t.request, None);
-                    let _ = t.request.take().unwrap().wait()
-                        .map_err(|e| {
-                            warn!("got error 5: \t {}", e);
-                        })
-                        .and_then(|r| {
-                            debug!("got r on ReadExecutor: \t {:?}", r);
-                            let qtd = match r {
-                                Ok(rx) => match rx.recv() {
-                                    Ok(rst) => match rst {
-                                        Ok(v) => v,
-                                        Err(e) => {
-                                            warn!("got error: \t {}", e);
-                                            -1i64
-                                        },
-                                    }
-                                Err(e) => {
-                                    warn!("got error: \t {}", e);
-                                    -1i64
-                                }},
-                                Err(e) => {
-                                    warn!("got error: \t {}", e);
-                                    -1i64
-                                }
-                            };
-                            match tx.send(qtd) {
-                                Ok(_) => trace!("qtd sent on ReadExecutor"),
-                                Err(e) => warn!("got error 6: \t {}", e),
-                            }
-                            Ok(())
-                        });
-                    debug!("waiting for rx in ReadExecutor");
-                    let qtd = match rx.recv() {
-                        Ok(qtd) => qtd,
-                        Err(e) => {
-                            warn!("got error 7: \t {}", e);                            
-                            0
-                        }
-                    };
+                    let qtd = wait_films(t.request.take().unwrap(), deadline);
 
                     json!({"uuid": t.uuid,
                            "name": t.name,
                            "climate": t.climate,
                            "terrain": t.terrain,
-                           "films": qtd
+                           "films": qtd.count,
+                           "enrichment": qtd.enrichment
                           })
                     
                 })
@@ -483,8 +1114,9 @@ impl Handler<GetPlanet> for ReadExecutor {
     fn handle(&mut self, id : GetPlanet, _: &mut Self::Context) -> Self::Result {    
         let ref mut store = self.store;
         let films = {self.films.clone()};
+        let deadline = Instant::now() + self.films_wait;
 
-        let results = QueryBuilder::new(store, "[:find [?n, ?c, ?t]
+        let results = QueryBuilder::new(store, "[:find [?x, ?n, ?c, ?t]
                                   :in ?id
                                   :where [?x :planet/uuid ?id]
                                          [?x :planet/name ?n]
@@ -496,59 +1128,109 @@ impl Handler<GetPlanet> for ReadExecutor {
 
         let out = { 
             if let Some(rec) = results {
-                let (tx, rx) = bounded::<i64>(1);
-                let n = rec[0].clone().into_string().expect("data avaliable").deref().clone();
-                let _ = films.send(ReadFilms(n.clone())).wait()
-                        .map_err(|e| {
-                            warn!("got error 5: \t {}", e);
-                        })
-                        .and_then(|r| {
-                            debug!("got r on ReadExecutor: \t {:?}", r);
-                            let qtd = match r {
-                                Ok(rx) => match rx.recv() {
-                                    Ok(rst) => match rst {
-                                        Ok(v) => v,
-                                        Err(e) => {
-                                            warn!("got error: \t {}", e);
-                                            -1i64
-                                        },
-                                    }
-                                Err(e) => {
-                                    warn!("got error: \t {}", e);
-                                    -1i64
-                                }},
-                                Err(e) => {
-                                    warn!("got error: \t {}", e);
-                                    -1i64
-                                }
-                            };
-                            match tx.send(qtd) {
-                                Ok(_) => trace!("qtd sent on ReadExecutor"),
-                                Err(e) => warn!("got error 6: \t {}", e),
-                            }
-                            Ok(())
-                        });
-                debug!("waiting for rx in ReadExecutor");
-                let qtd = match rx.recv() {
-                    Ok(qtd) => qtd,
-                    Err(e) => {
-                        warn!("got error 7: \t {}", e);                            
-                        -1
-                    }
+                // the entity comes with the row, a planet deleted meanwhile is just not found
+                let e = match rec[0] {
+                    Binding::Scalar(TypedValue::Ref(e)) => e,
+                    _ => return Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", id.0)))),
                 };
+                let n = rec[1].clone().into_string().expect("data avaliable").deref().clone();
+                let qtd = wait_films(films.send(ReadFilms(n.clone())), deadline);
 
-                    json!({"uuid": id.0,
-                           "name": n,
-                           "climate": rec[1].clone().into_string(),
-                           "terrain": rec[2].clone().into_string(),
-                           "films": qtd
-                          })
+                json!({"uuid": id.0,
+                       "name": n,
+                       "climate": rec[2].clone().into_string(),
+                       "terrain": rec[3].clone().into_string(),
+                       "films": qtd.count,
+                       "enrichment": qtd.enrichment,
+                       "relations": relations::relations(&*store, "planet", e, &id.1)?
+                      })
                 
             } else {
-                Value::Null
+                return Err(Error::from_kind(ErrorKind::NotFound(format!("planet not found: {}", id.0))));
             }
 
         };
         Ok(json!(out))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<(String, String)> {
+        ["Alderaan", "Bespin", "Dagobah", "Endor", "Hoth"].iter().enumerate()
+            .map(|(i, n)| (format!("{}", i), String::from(*n)))
+            .collect()
+    }
+
+    fn page(keyset: Option<&Keyset>, offset: usize) -> (Vec<String>, Option<Keyset>, Option<Keyset>) {
+        let (rows, next, previous) = window(rows(), PlanetSort::Name, keyset, offset, 2,
+                                            |r| PlanetSort::Name.keyset(&r.0, &r.1, ""));
+        (rows.into_iter().map(|r| r.1).collect(), next, previous)
+    }
+
+    #[test]
+    fn window_pages_forward_and_back() {
+        let (first, next, previous) = page(None, 0);
+        assert_eq!(first, vec!["Alderaan", "Bespin"]);
+        assert_eq!(previous, None);
+        let next = next.unwrap();
+        assert_eq!((next.keys.clone(), next.before), (vec![String::from("Bespin")], false));
+
+        let (second, next, previous) = page(Some(&next), 0);
+        assert_eq!(second, vec!["Dagobah", "Endor"]);
+        let (last, end, _) = page(next.as_ref(), 0);
+        assert_eq!((last, end), (vec![String::from("Hoth")], None));
+
+        let previous = previous.unwrap();
+        assert!(previous.before);
+        let (back, _, start) = page(Some(&previous), 0);
+        assert_eq!((back, start), (vec![String::from("Alderaan"), String::from("Bespin")], None));
+    }
+
+    #[test]
+    fn window_survives_changes() {
+        // a removed boundary row still positions the next page
+        let at = PlanetSort::Name.keyset("9", "Corellia", "");
+        let (rows, _, _) = page(Some(&at), 0);
+        assert_eq!(rows, vec!["Dagobah", "Endor"]);
+        let (rows, _, previous) = page(None, 4);
+        assert_eq!(rows, vec!["Hoth"]);
+        assert!(previous.is_some());
+    }
+
+    #[test]
+    fn descending_keysets_compare_in_reverse() {
+        let sort = PlanetSort::NameDesc;
+        let (a, b) = (sort.keyset("1", "Alderaan", ""), sort.keyset("2", "Bespin", ""));
+        assert_eq!(sort.compare(&a, &b), Ordering::Greater);
+        let climate = PlanetSort::Climate;
+        assert_eq!(climate.compare(&climate.keyset("1", "Hoth", "frozen"), &climate.keyset("2", "Bespin", "temperate")),
+                   Ordering::Less);
+    }
+
+    fn planet(name: &str, films: usize) -> SearchResult {
+        SearchResult{name: String::from(name), films: vec![Value::Null; films]}
+    }
+
+    #[test]
+    fn search_counts_the_planet_named_exactly() {
+        let results = vec![planet("Tatooine Prime", 1), planet("TATOOINE", 5)];
+        match search_page("tatooine", &results, None) {
+            Ok(FilmsPage::Found(count)) => assert_eq!(count, 5),
+            other => panic!("unexpected {:?}", other),
+        }
+        match search_page("Tatooine", &[planet("Tatooine Prime", 1)], Some(String::from("http://up/planets/?page=2"))) {
+            Ok(FilmsPage::Next(url)) => assert_eq!(url, "http://up/planets/?page=2"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match search_page("Tatooine", &[planet("Tatooine Prime", 1)], None) {
+            Err(ref e) => match *e.kind() {
+                ErrorKind::NotFound(_) => (),
+                _ => panic!("unexpected {:?}", e),
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
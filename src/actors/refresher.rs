@@ -0,0 +1,181 @@
+use actix::{Actor, AsyncContext, Context, ActorFuture, Handler, WrapFuture, fut, Addr, Syn, SpawnHandle};
+use domain::{PlanetNames, RefreshFilms, WarmPlanets, films_key};
+use std::cmp;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::Duration;
+use super::{ReadExecutor, FilmsExecutor};
+
+/// Shortest time between two refreshes, so a refresh run never bursts upstream
+/// and trips its circuit breaker.
+const MIN_STEP_MS: u64 = 100;
+
+/// Planets waiting for a refresh, each queued once by films key. Warm-ups go
+/// first at the shortest step, periodic refreshes are spread over the interval.
+struct Queue {
+    warm: VecDeque<String>,
+    periodic: VecDeque<String>,
+    queued: BTreeSet<String>,
+    step: Duration,
+}
+
+impl Queue {
+    fn new() -> Queue {
+        Queue{warm: VecDeque::new(), periodic: VecDeque::new(), queued: BTreeSet::new()
+            , step: Duration::from_millis(MIN_STEP_MS)}
+    }
+
+    fn periodic(&mut self, names: Vec<String>, every: Option<Duration>) {
+        for name in names {
+            if self.queued.insert(films_key(&name)) {
+                self.periodic.push_back(name);
+            }
+        }
+        self.step = step(every, self.periodic.len());
+    }
+
+    /// Queues ahead of the periodic refreshes, taking the planets already
+    /// waiting there along.
+    fn warm(&mut self, names: Vec<String>) {
+        for name in names {
+            let key = films_key(&name);
+            if !self.queued.insert(key.clone()) {
+                if self.warm.iter().any(|n| films_key(n) == key) {
+                    continue;
+                }
+                self.periodic.retain(|n| films_key(n) != key);
+            }
+            self.warm.push_back(name);
+        }
+    }
+
+    /// Next planet to refresh and the time to wait before the one after it.
+    fn pop(&mut self) -> Option<(String, Duration)> {
+        let next = match self.warm.pop_front() {
+            Some(name) => Some((name, Duration::from_millis(MIN_STEP_MS))),
+            None => self.periodic.pop_front().map(|name| (name, self.step)),
+        };
+        if let Some((ref name, _)) = next {
+            self.queued.remove(&films_key(name));
+        }
+        next
+    }
+}
+
+/// Time between two refreshes of a queue of `queued` planets, spread over the
+/// refresh interval if any.
+fn step(every: Option<Duration>, queued: usize) -> Duration {
+    let min = Duration::from_millis(MIN_STEP_MS);
+    match every {
+        Some(every) => cmp::max(min, every / cmp::max(queued, 1) as u32),
+        None => min,
+    }
+}
+
+/// Re-fetches the films of planets whose cached count is missing or stale
+/// one at a time, so readers seldom wait on upstream. Every planet in the
+/// store is queued each `every`, its refreshes spread over the interval.
+pub struct FilmsRefresher {
+    read: Addr<Syn, ReadExecutor>,
+    films: Addr<Syn, FilmsExecutor>,
+    every: Option<Duration>,
+    queue: Queue,
+    next: Option<SpawnHandle>,
+}
+
+impl FilmsRefresher {
+    pub fn new(read: Addr<Syn, ReadExecutor>, films: Addr<Syn, FilmsExecutor>, every: Option<Duration>) -> FilmsRefresher {
+        FilmsRefresher{read: read, films: films, every: every, queue: Queue::new(), next: None}
+    }
+
+    fn refresh(&mut self, ctx: &mut Context<Self>) {
+        ctx.spawn(self.read.send(PlanetNames)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(Ok(names)) => {
+                        debug!("refreshing films of {} planets", names.len());
+                        let every = act.every;
+                        act.queue.periodic(names, every);
+                        act.start(ctx);
+                    }
+                    Ok(Err(e)) => warn!("got error listing planets to refresh: \t {}", e),
+                    Err(e) => warn!("got error listing planets to refresh: \t {}", e),
+                }
+                fut::ok(())
+            }));
+    }
+
+    fn start(&mut self, ctx: &mut Context<Self>) {
+        if self.next.is_none() {
+            self.drain(ctx);
+        }
+    }
+
+    fn drain(&mut self, ctx: &mut Context<Self>) {
+        match self.queue.pop() {
+            Some((name, wait)) => {
+                self.films.do_send(RefreshFilms(name));
+                self.next = Some(ctx.run_later(wait, |act, ctx| act.drain(ctx)));
+            }
+            None => self.next = None,
+        }
+    }
+}
+
+impl Actor for FilmsRefresher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(every) = self.every {
+            ctx.run_interval(every, |act, ctx| act.refresh(ctx));
+        }
+    }
+}
+
+impl Handler<WarmPlanets> for FilmsRefresher {
+    type Result = ();
+
+    fn handle(&mut self, msg: WarmPlanets, ctx: &mut Self::Context) {
+        self.queue.warm(msg.0);
+        // don't sit out the spread step of a periodic refresh already waiting
+        match self.next.take() {
+            Some(next) => {
+                ctx.cancel_future(next);
+                self.next = Some(ctx.run_later(Duration::from_millis(MIN_STEP_MS), |act, ctx| act.drain(ctx)));
+            }
+            None => self.start(ctx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| String::from(*n)).collect()
+    }
+
+    #[test]
+    fn spreads_refreshes_over_the_interval() {
+        let hour = Duration::from_secs(3600);
+        assert_eq!(step(Some(hour), 60), Duration::from_secs(60));
+        assert_eq!(step(Some(hour), 0), hour);
+        assert_eq!(step(Some(hour), 1000000), Duration::from_millis(MIN_STEP_MS));
+        assert_eq!(step(None, 10), Duration::from_millis(MIN_STEP_MS));
+    }
+
+    #[test]
+    fn warms_up_right_away() {
+        let min = Duration::from_millis(MIN_STEP_MS);
+        let mut queue = Queue::new();
+        queue.periodic(names(&["Alderaan", "Bespin", "Hoth"]), Some(Duration::from_secs(3600)));
+        queue.warm(names(&["Endor", "hoth", "Endor"]));
+
+        assert_eq!(queue.pop(), Some((String::from("Endor"), min)));
+        assert_eq!(queue.pop(), Some((String::from("hoth"), min)));
+        assert_eq!(queue.pop(), Some((String::from("Alderaan"), Duration::from_secs(1200))));
+        assert_eq!(queue.pop(), Some((String::from("Bespin"), Duration::from_secs(1200))));
+        assert_eq!(queue.pop(), None);
+    }
+}
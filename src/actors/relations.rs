@@ -0,0 +1,189 @@
+use actix::Handler;
+use domain::resources::{Relation, RELATIONS, relation, GetRelated, Link};
+use errors::{Result, Error, ErrorKind};
+use mentat::{TxReport, TypedValue, Binding, Queryable, IntoResult, QueryInputs, KnownEntid
+    , entity_builder::{BuildTerms, TermBuilder}};
+use serde_json::value::{Value, Map};
+use uuid::Uuid;
+use super::{WriterExecutor, ReadExecutor};
+use super::resources::{binding_json, attribute_name, attribute};
+
+/// Deepest `expand` path accepted, one segment per level.
+pub const MAX_EXPAND_DEPTH: usize = 3;
+
+/// Entity of namespace `ns` identified by `uuid`.
+pub fn entid<Q: Queryable>(q: &Q, ns: &str, uuid: Uuid) -> Result<Option<i64>> {
+    match q.q_once(&format!("[:find ?e .
+                              :in ?id
+                              :where [?e :{}/uuid ?id]]", ns),
+                   QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Ref(e))) => Ok(Some(e)),
+        _ => Ok(None),
+    }
+}
+
+/// Entities referenced by `e` through the ref attribute `attr`.
+fn targets<Q: Queryable>(q: &Q, attr: &str, e: i64) -> Result<Vec<i64>> {
+    Ok(q.q_once(&format!("[:find [?t ...]
+                           :in ?e
+                           :where [?e {} ?t]]", attr),
+                QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))]))
+        .into_coll_result()?
+        .into_iter()
+        .filter_map(|b| match b {
+            Binding::Scalar(TypedValue::Ref(t)) => Some(t),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Sub paths of `expand` below `name`, `None` when `name` is not expanded.
+fn expanded(expand: &[String], name: &str) -> Option<Vec<String>> {
+    let mut found = false;
+    let mut rest = Vec::new();
+    for path in expand {
+        let mut segments = path.splitn(2, '.');
+        if segments.next() == Some(name) {
+            found = true;
+            if let Some(sub) = segments.next() {
+                rest.push(String::from(sub));
+            }
+        }
+    }
+    if found { Some(rest) } else { None }
+}
+
+/// Every attribute of `e` as a json object, ref attributes are reported under `relations`.
+pub fn pull<Q: Queryable>(q: &Q, ns: &str, e: i64, expand: &[String]) -> Result<Value> {
+    let mut entity = Map::new();
+    for row in q.q_once("[:find ?ident ?v
+                          :in ?e
+                          :where [?e ?a ?v]
+                                 [?a :db/ident ?ident]]",
+                        QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))]))
+                .into_rel_result()?
+                .into_iter() {
+        let mut row = row.into_iter();
+        if let (Some(ident), Some(v)) = (row.next(), row.next()) {
+            if let Some(attr) = attribute_name(&ident) {
+                if relation(ns, &attr).is_none() {
+                    entity.insert(attr, binding_json(v));
+                }
+            }
+        }
+    }
+    entity.insert(String::from("relations"), relations(q, ns, e, expand)?);
+    Ok(Value::Object(entity))
+}
+
+fn related<Q: Queryable>(q: &Q, rel: &Relation, e: i64, expand: &[String]) -> Result<Value> {
+    let sub = expanded(expand, rel.name);
+    let mut out = Vec::new();
+    for t in targets(q, &rel.attribute(), e)? {
+        out.push(match sub {
+            Some(ref sub) => pull(q, rel.to, t, sub)?,
+            None => match q.q_once(&format!("[:find ?u . :in ?t :where [?t :{}/uuid ?u]]", rel.to),
+                                   QueryInputs::with_value_sequence(vec![(var!(?t), TypedValue::Ref(t))]))
+                           .into_scalar_result()? {
+                Some(u) => binding_json(u),
+                None => continue,
+            },
+        });
+    }
+    if rel.many {
+        Ok(Value::Array(out))
+    } else {
+        Ok(out.pop().unwrap_or(Value::Null))
+    }
+}
+
+/// Relations of `e` keyed by relation name, as uuids or inlined entities when expanded.
+pub fn relations<Q: Queryable>(q: &Q, ns: &str, e: i64, expand: &[String]) -> Result<Value> {
+    let mut out = Map::new();
+    for rel in RELATIONS.iter().filter(|r| r.from == ns) {
+        out.insert(String::from(rel.name), related(q, rel, e, expand)?);
+    }
+    Ok(Value::Object(out))
+}
+
+fn unknown(ns: &str, name: &str) -> Error {
+    Error::from_kind(ErrorKind::NotFound(format!("unknown {} relation: {}", ns, name)))
+}
+
+impl Handler<GetRelated> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: GetRelated, _: &mut Self::Context) -> Self::Result {
+        let rel = relation(msg.ns, &msg.relation).ok_or_else(|| unknown(msg.ns, &msg.relation))?;
+        let e = entid(&self.store, msg.ns, msg.uuid)?
+            .ok_or_else(|| Error::from_kind(ErrorKind::NotFound(format!("{} not found: {}", msg.ns, msg.uuid))))?;
+        // related entities are always inlined, `expand` paths are relative to them
+        let mut expand : Vec<String> = msg.expand.iter().map(|p| format!("{}.{}", rel.name, p)).collect();
+        expand.push(String::from(rel.name));
+        related(&self.store, rel, e, &expand)
+    }
+}
+
+impl Handler<Link> for WriterExecutor {
+    type Result = Result<TxReport>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: Link, _: &mut Self::Context) -> Self::Result {
+        let res = self.link(msg);
+        self.published(res)
+    }
+}
+
+impl WriterExecutor {
+    fn link(&mut self, msg: Link) -> Result<TxReport> {
+        let rel = relation(msg.ns, &msg.relation).ok_or_else(|| unknown(msg.ns, &msg.relation))?;
+        let mut ip = self.store.begin_transaction()?;
+        let a = entid(&ip, rel.from, msg.uuid)?
+            .ok_or_else(|| Error::from_kind(ErrorKind::NotFound(format!("{} not found: {}", rel.from, msg.uuid))))?;
+        let b = entid(&ip, rel.to, msg.target)?
+            .ok_or_else(|| Error::from_kind(ErrorKind::NotFound(format!("{} not found: {}", rel.to, msg.target))))?;
+        let attr = rel.attribute();
+        let inverse = rel.inverse();
+
+        let (r_attr, r_inverse) = (attribute(&ip, &attr)?, match inverse {
+            Some(ref inv) => Some(attribute(&ip, &inv.attribute())?),
+            None => None,
+        });
+
+        let mut builder = TermBuilder::new();
+        if msg.link {
+            builder.add(KnownEntid(a), r_attr, TypedValue::Ref(b))?;
+            if let (Some(inv), Some(r_inv)) = (inverse, r_inverse) {
+                builder.add(KnownEntid(b), r_inv, TypedValue::Ref(a))?;
+                // cardinality one on either side moves the link away from its previous entity
+                if !rel.many {
+                    for old in targets(&ip, &attr, a)?.into_iter().filter(|old| *old != b) {
+                        builder.retract(KnownEntid(old), r_inv, TypedValue::Ref(a))?;
+                    }
+                }
+                if !inv.many {
+                    for old in targets(&ip, &inv.attribute(), b)?.into_iter().filter(|old| *old != a) {
+                        builder.retract(KnownEntid(old), r_attr, TypedValue::Ref(b))?;
+                    }
+                }
+            }
+        } else {
+            if !targets(&ip, &attr, a)?.contains(&b) {
+                return Err(Error::from_kind(ErrorKind::NotFound(
+                    format!("{} {} is not linked to {}", rel.to, msg.target, msg.uuid))));
+            }
+            builder.retract(KnownEntid(a), r_attr, TypedValue::Ref(b))?;
+            if let (Some(inv), Some(r_inv)) = (inverse, r_inverse) {
+                if targets(&ip, &inv.attribute(), b)?.contains(&a) {
+                    builder.retract(KnownEntid(b), r_inv, TypedValue::Ref(a))?;
+                }
+            }
+        }
+
+        let res = ip.transact_builder(builder).map_err(Into::into);
+        let _ = ip.commit()?;
+        res
+    }
+}
@@ -0,0 +1,225 @@
+use actix::Handler;
+use domain::Created;
+use domain::resources::{Resource, FieldType, CreateResource, ReadResources, SearchResource
+    , GetResource, DeleteResource, relation};
+use errors::{Result, Error, ErrorKind};
+use mentat::{TxReport, TypedValue, Binding, QueryBuilder, Queryable, IntoResult, QueryInputs, KnownEntid
+    , entity_builder::{BuildTerms, TermBuilder}};
+use serde_json::{self, value::{Value, Map}};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use super::{WriterExecutor, ReadExecutor, eav};
+use super::relations::{entid, pull};
+
+/// Json representation of a query binding.
+pub fn binding_json(b: Binding) -> Value {
+    match b {
+        Binding::Scalar(v) => typed_json(v),
+        _ => Value::Null,
+    }
+}
+
+pub fn typed_json(v: TypedValue) -> Value {
+    match v {
+        TypedValue::String(s) => json!(s.as_str()),
+        TypedValue::Long(n) => json!(n),
+        TypedValue::Boolean(b) => json!(b),
+        TypedValue::Double(d) => json!(d.into_inner()),
+        TypedValue::Uuid(u) => json!(u.to_string()),
+        TypedValue::Instant(i) => json!(i),
+        TypedValue::Keyword(k) => json!(format!("{}", k)),
+        TypedValue::Ref(e) => json!(e),
+        #[allow(unreachable_patterns)]
+        _ => Value::Null,
+    }
+}
+
+/// Attribute name without namespace, `:person/name` becomes `name`.
+pub fn attribute_name(ident: &Binding) -> Option<String> {
+    match *ident {
+        Binding::Scalar(TypedValue::Keyword(ref k)) => {
+            let k = format!("{}", k);
+            k.rsplit('/').next().map(String::from)
+        }
+        _ => None,
+    }
+}
+
+/// Entid of a schema attribute, the `ident` is built from resource definitions
+/// and never from request data.
+pub fn attribute<Q: Queryable>(q: &Q, ident: &str) -> Result<KnownEntid> {
+    match q.q_once(&format!("[:find ?a . :where [?a :db/ident {}]]", ident), None).into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Ref(a))) => Ok(KnownEntid(a)),
+        _ => Err(Error::from_kind(ErrorKind::Msg(format!("unknown attribute: {}", ident)))),
+    }
+}
+
+/// Groups `[?u ?ident ?v]` rows into one json object per entity uuid, ref
+/// attributes are left to the relation endpoints.
+pub fn group(ns: &str, rows: Vec<Vec<Binding>>) -> Vec<Value> {
+    let mut entities : BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+    for row in rows {
+        let mut row = row.into_iter();
+        match (row.next(), row.next(), row.next()) {
+            (Some(u), Some(ident), Some(v)) => {
+                let uuid = match binding_json(u) {
+                    Value::String(u) => u,
+                    _ => continue,
+                };
+                match attribute_name(&ident) {
+                    Some(ref attr) if relation(ns, attr).is_some() => (),
+                    Some(attr) => {
+                        entities.entry(uuid).or_insert_with(Map::new).insert(attr, binding_json(v));
+                    }
+                    None => (),
+                }
+            }
+            _ => warn!("Incorrect query shape for resource rows."),
+        }
+    }
+    entities.into_iter().map(|(_, e)| Value::Object(e)).collect()
+}
+
+fn sort_by_name<T: Resource>(mut entities: Vec<Value>) -> Value {
+    entities.sort_by(|a, b| {
+        let a = a.get(T::NAME).and_then(|n| n.as_str()).unwrap_or("");
+        let b = b.get(T::NAME).and_then(|n| n.as_str()).unwrap_or("");
+        a.cmp(b)
+    });
+    Value::Array(entities)
+}
+
+impl<T: Resource> Handler<CreateResource<T>> for WriterExecutor {
+    type Result = Result<Created>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: CreateResource<T>, _: &mut Self::Context) -> Self::Result {
+        let ns = T::NAMESPACE;
+        let fields = match serde_json::to_value(&msg.0)? {
+            Value::Object(fields) => fields,
+            _ => return Err(Error::from_kind(ErrorKind::BadRequest(format!("{} must be an object", ns)))),
+        };
+        match fields.get(T::NAME) {
+            Some(&Value::String(ref name)) if !name.is_empty() => (),
+            _ => return Err(Error::from_kind(ErrorKind::BadRequest(format!("{} {} is required", ns, T::NAME)))),
+        }
+
+        let uuid = Uuid::new_v4();
+        let ip = self.store.begin_transaction()?;
+        let mut values = vec![(attribute(&ip, &format!(":{}/uuid", ns))?, TypedValue::from(uuid))];
+        for &(field, ty) in T::FIELDS {
+            let v = match (ty, fields.get(field)) {
+                (FieldType::Text, Some(&Value::String(ref v))) => TypedValue::from(v.clone()),
+                (FieldType::Long, Some(&Value::Number(ref v))) if v.is_i64() => TypedValue::Long(v.as_i64().unwrap_or(0)),
+                (_, None) | (_, Some(&Value::Null)) => continue,
+                (_, Some(v)) => return Err(Error::from_kind(
+                    ErrorKind::BadRequest(format!("unexpected value for {}: {}", field, v)))),
+            };
+            values.push((attribute(&ip, &format!(":{}/{}", ns, field))?, v));
+        }
+
+        let mut builder = ip.builder().describe_tempid("x");
+        for (a, v) in values {
+            builder.add(a, v)?;
+        }
+        let report = builder.commit()?;
+        Ok(Created{report: report, uuid: uuid})
+    }
+}
+
+impl<T: Resource> Handler<DeleteResource<T>> for WriterExecutor {
+    type Result = Result<TxReport>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: DeleteResource<T>, _: &mut Self::Context) -> Self::Result {
+        // refs retracted from planets are planet updates
+        let res = self.delete_resource::<T>(msg.0);
+        self.published(res)
+    }
+}
+
+impl WriterExecutor {
+    fn delete_resource<T: Resource>(&mut self, uuid: Uuid) -> Result<TxReport> {
+        let ns = T::NAMESPACE;
+        let mut ip = self.store.begin_transaction()?;
+        let mut builder = TermBuilder::new();
+        let mut datoms : Vec<_> = ip.q_once(&format!("[:find ?e ?a ?v
+                                                       :in ?id
+                                                       :where [?e ?a ?v][?e :{}/uuid ?id]]", ns),
+                                            QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+                                    .into_rel_result()?
+                                    .into_iter()
+                                    .map(eav)
+                                    .collect();
+        if datoms.is_empty() {
+            return Err(Error::from_kind(ErrorKind::NotFound(format!("{} not found: {}", ns, uuid))));
+        }
+        // refs from other entities pointing to the deleted one
+        datoms.extend(ip.q_once(&format!("[:find ?x ?a ?e
+                                           :in ?id
+                                           :where [?e :{}/uuid ?id][?x ?a ?e]]", ns),
+                                QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+                        .into_rel_result()?
+                        .into_iter()
+                        .map(eav));
+        for (e, a, v) in datoms {
+            builder.retract(e, a, v)?;
+        }
+
+        let res = ip.transact_builder(builder).map_err(Into::into);
+        let _ = ip.commit()?;
+        res
+    }
+}
+
+impl<T: Resource> Handler<ReadResources<T>> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: ReadResources<T>, _: &mut Self::Context) -> Self::Result {
+        let ref mut store = self.store;
+        let rows : Vec<Vec<Binding>> =
+            QueryBuilder::new(store, format!("[:find ?u ?ident ?v
+                                               :where [?x :{}/uuid ?u]
+                                                      [?x ?a ?v]
+                                                      [?a :db/ident ?ident]]", T::NAMESPACE))
+                .execute_rel()?
+                .into_iter()
+                .collect();
+        Ok(sort_by_name::<T>(group(T::NAMESPACE, rows)))
+    }
+}
+
+impl<T: Resource> Handler<SearchResource<T>> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, search: SearchResource<T>, _: &mut Self::Context) -> Self::Result {
+        let ref mut store = self.store;
+        let s = format!("*{}*", search.0);
+        let rows : Vec<Vec<Binding>> =
+            QueryBuilder::new(store, format!("[:find ?u ?ident ?v
+                                               :in ?search
+                                               :where [(fulltext $ :{}/{} ?search) [[?x _ _ _]]]
+                                                      [?x :{}/uuid ?u]
+                                                      [?x ?a ?v]
+                                                      [?a :db/ident ?ident]]", T::NAMESPACE, T::NAME, T::NAMESPACE))
+                .bind_value("?search", s)
+                .execute_rel()?
+                .into_iter()
+                .collect();
+        Ok(sort_by_name::<T>(group(T::NAMESPACE, rows)))
+    }
+}
+
+impl<T: Resource> Handler<GetResource<T>> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, id: GetResource<T>, _: &mut Self::Context) -> Self::Result {
+        match entid(&self.store, T::NAMESPACE, id.0)? {
+            Some(e) => pull(&self.store, T::NAMESPACE, e, &id.1),
+            None => Err(Error::from_kind(ErrorKind::NotFound(format!("{} not found: {}", T::NAMESPACE, id.0)))),
+        }
+    }
+}
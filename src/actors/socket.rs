@@ -0,0 +1,160 @@
+use actix::{Actor, ActorContext, AsyncContext, Handler, StreamHandler, ActorFuture, WrapFuture, fut, Addr, Syn};
+use actix_web::ws;
+use domain::{PlanetEvent, SearchPlanet, Watch};
+use errors::{Error, ErrorKind};
+use serde_json::{self, value::Value};
+use std::collections::BTreeMap;
+use txlog::Change;
+use super::State;
+
+/// Message sent by a websocket client, `{"type": "subscribe", "id": "tato", "search": "Tato"}`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe { id: String, search: String },
+    Unsubscribe { id: String },
+}
+
+fn error_text(id: Option<&str>, e: &Error) -> String {
+    let kind = e.kind();
+    json!({
+        "type": "error",
+        "id": id,
+        "error": {
+            "code": kind.code(),
+            "message": kind.message(),
+        }
+    }).to_string()
+}
+
+/// Search subscriptions of one websocket client, a subscription result set is
+/// pushed again whenever a committed planet change alters it.
+#[derive(Default)]
+pub struct PlanetSocket {
+    /// Search term and last pushed results by subscription id.
+    subscriptions: BTreeMap<String, (String, Option<Value>)>,
+}
+
+impl Actor for PlanetSocket {
+    type Context = ws::WebsocketContext<Self, State>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let addr : Addr<Syn, _> = ctx.address();
+        ctx.state().events.do_send(Watch(addr.recipient()));
+    }
+}
+
+impl PlanetSocket {
+    /// Runs the search of subscription `id`, pushing its results when they
+    /// differ from the last ones pushed.
+    fn evaluate(&mut self, id: String, ctx: &mut ws::WebsocketContext<Self, State>) {
+        let search = match self.subscriptions.get(&id) {
+            Some(&(ref search, _)) => search.clone(),
+            None => return,
+        };
+        let read = ctx.state().read.clone();
+        ctx.spawn(read.send(SearchPlanet(search))
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(Ok(results)) => if let Some(sub) = act.subscriptions.get_mut(&id) {
+                        if sub.1.as_ref() != Some(&results) {
+                            ctx.text(json!({"type": "result", "id": id, "results": results}).to_string());
+                            sub.1 = Some(results);
+                        }
+                    },
+                    Ok(Err(e)) => ctx.text(error_text(Some(&id), &e)),
+                    Err(e) => warn!("got error evaluating subscription {}: \t {}", id, e),
+                }
+                fut::ok(())
+            }));
+    }
+}
+
+/// Whether `event` may change the result set of `search`: the planet is listed
+/// in it, or its new name has every word of the search term. Deletions only
+/// matter to the subscriptions listing the deleted planet.
+fn affected(search: &str, results: &Option<Value>, event: &PlanetEvent) -> bool {
+    let uuid = event.uuid.hyphenated().to_string();
+    let listed = match *results {
+        Some(Value::Array(ref planets)) => planets.iter().any(|p| p.get("uuid").and_then(|u| u.as_str()) == Some(&uuid)),
+        _ => return true,
+    };
+    if listed || event.change == Change::Deleted {
+        return listed;
+    }
+    match event.name {
+        Some(ref name) => {
+            let name = name.to_lowercase();
+            search.to_lowercase().split_whitespace().all(|word| name.contains(word))
+        }
+        None => true,
+    }
+}
+
+impl Handler<PlanetEvent> for PlanetSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: PlanetEvent, ctx: &mut Self::Context) -> Self::Result {
+        let ids : Vec<String> = self.subscriptions.iter()
+            .filter(|&(_, &(ref search, ref results))| affected(search, results, &msg))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in ids {
+            self.evaluate(id, ctx);
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for PlanetSocket {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(ClientMessage::Subscribe{id, search}) => {
+                    debug!("subscribing {} to search: \t {}", id, search);
+                    self.subscriptions.insert(id.clone(), (search, None));
+                    self.evaluate(id, ctx);
+                }
+                Ok(ClientMessage::Unsubscribe{id}) => match self.subscriptions.remove(&id) {
+                    Some(_) => ctx.text(json!({"type": "unsubscribed", "id": id}).to_string()),
+                    None => ctx.text(error_text(Some(&id), &Error::from_kind(
+                        ErrorKind::NotFound(format!("subscription not found: {}", id))))),
+                },
+                Err(e) => ctx.text(error_text(None, &Error::from_kind(
+                    ErrorKind::BadRequest(format!("invalid message: {}", e))))),
+            },
+            ws::Message::Close(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn event(uuid: Uuid, change: Change, name: Option<&str>) -> PlanetEvent {
+        PlanetEvent{tx: 1, instant: Utc::now(), uuid: uuid, change: change, name: name.map(String::from)}
+    }
+
+    #[test]
+    fn listed_planets_affect_their_subscriptions() {
+        let uuid = Uuid::new_v4();
+        let results = Some(json!([{"uuid": uuid.hyphenated().to_string(), "name": "Tatooine"}]));
+        assert!(affected("tato", &results, &event(uuid, Change::Updated, Some("Hoth"))));
+        assert!(affected("tato", &results, &event(uuid, Change::Deleted, None)));
+        assert!(!affected("tato", &results, &event(Uuid::new_v4(), Change::Deleted, None)));
+    }
+
+    #[test]
+    fn other_planets_affect_matching_searches_only() {
+        let results = Some(json!([]));
+        assert!(affected("tato", &results, &event(Uuid::new_v4(), Change::Created, Some("Tatooine"))));
+        assert!(!affected("tato", &results, &event(Uuid::new_v4(), Change::Created, Some("Hoth"))));
+        assert!(affected("tato", &results, &event(Uuid::new_v4(), Change::Updated, None)));
+        assert!(affected("tato", &None, &event(Uuid::new_v4(), Change::Updated, Some("Hoth"))));
+    }
+}
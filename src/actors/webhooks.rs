@@ -0,0 +1,360 @@
+use actix::{Actor, AsyncContext, Context, Handler, ActorFuture, WrapFuture, fut, Addr, Syn};
+use actix_web::client;
+use chrono::Utc;
+use domain::{Created, PlanetEvent, Watch};
+use domain::webhooks::{RegisterWebhook, WebhookSecret, ReadWebhooks, DeleteWebhook, ForgetWebhook, WebhookDeliveries
+    , Target, WebhookTargets};
+use errors::{Result, Error, ErrorKind};
+use futures::Future;
+use hmac::{Hmac, Mac};
+use mentat::{TxReport, TypedValue, Binding, Queryable, IntoResult, QueryInputs
+    , entity_builder::{BuildTerms, TermBuilder}};
+use outbox::{Outbox, Delivery};
+use serde_json::value::Value;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use uuid::Uuid;
+use super::{WriterExecutor, ReadExecutor, eav};
+use super::events::EventBroker;
+
+/// Interval between lookups of due deliveries, retries included.
+const SWEEP_SECS: u64 = 5;
+const DELIVERY_TIMEOUT_SECS: u64 = 10;
+
+/// Hex encoded HMAC-SHA256 of `payload`, sent as `X-Swapi-Signature: sha256=<hex>`.
+pub fn sign(secret: &str, payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.input(payload);
+    mac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn webhook_entid<Q: Queryable>(q: &Q, uuid: Uuid) -> Result<i64> {
+    match q.q_once("[:find ?w . :in ?id :where [?w :webhook/uuid ?id]]",
+                   QueryInputs::with_value_sequence(vec![(var!(?id), uuid.into())]))
+           .into_scalar_result()? {
+        Some(Binding::Scalar(TypedValue::Ref(w))) => Ok(w),
+        _ => Err(Error::from_kind(ErrorKind::NotFound(format!("webhook not found: {}", uuid)))),
+    }
+}
+
+fn text(b: Option<Binding>) -> String {
+    match b {
+        Some(Binding::Scalar(TypedValue::String(s))) => s.as_ref().clone(),
+        _ => String::new(),
+    }
+}
+
+fn uuid(b: Option<Binding>) -> Option<Uuid> {
+    match b {
+        Some(Binding::Scalar(TypedValue::Uuid(u))) => Some(u),
+        _ => None,
+    }
+}
+
+impl Handler<RegisterWebhook> for WriterExecutor {
+    type Result = Result<Created>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: RegisterWebhook, _: &mut Self::Context) -> Self::Result {
+        if !msg.url.starts_with("http://") && !msg.url.starts_with("https://") {
+            return Err(Error::from_kind(ErrorKind::BadRequest(format!("webhook url must be http or https: {}", msg.url))));
+        }
+        let report = {
+            let ip = self.store.begin_transaction()?;
+            let mut builder = ip.builder().describe_tempid("w");
+            builder.add_kw(&kw!(:webhook/uuid), TypedValue::from(msg.uuid))?;
+            builder.add_kw(&kw!(:webhook/url), TypedValue::from(msg.url))?;
+            builder.commit()?
+        };
+        Ok(Created{report: report, uuid: msg.uuid})
+    }
+}
+
+impl Handler<DeleteWebhook> for WriterExecutor {
+    type Result = Result<TxReport>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: DeleteWebhook, _: &mut Self::Context) -> Self::Result {
+        let mut ip = self.store.begin_transaction()?;
+        let w = webhook_entid(&ip, msg.0)?;
+        let mut builder = TermBuilder::new();
+        // the secret stored by earlier versions goes with the rest
+        for (e, a, v) in ip.q_once("[:find ?w ?a ?v :in ?w :where [?w ?a ?v]]",
+                                   QueryInputs::with_value_sequence(vec![(var!(?w), TypedValue::Ref(w))]))
+                           .into_rel_result()?
+                           .into_iter()
+                           .map(eav) {
+            builder.retract(e, a, v)?;
+        }
+
+        let res = ip.transact_builder(builder).map_err(Into::into);
+        let _ = ip.commit()?;
+        res
+    }
+}
+
+impl Handler<ReadWebhooks> for ReadExecutor {
+    type Result = Result<Value>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: ReadWebhooks, _: &mut Self::Context) -> Self::Result {
+        let webhooks : Vec<Value> = self.store.q_once("[:find ?u ?url
+                                                        :where [?w :webhook/uuid ?u]
+                                                               [?w :webhook/url ?url]]", None)
+            .into_rel_result()?
+            .into_iter()
+            .map(|row| {
+                let mut row = row.into_iter();
+                let uuid = row.next().map_or(Value::Null, super::resources::binding_json);
+                json!({"uuid": uuid, "url": text(row.next())})
+            })
+            .collect();
+        Ok(json!(webhooks))
+    }
+}
+
+impl Handler<WebhookTargets> for ReadExecutor {
+    type Result = Result<Vec<Target>>;
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, _: WebhookTargets, _: &mut Self::Context) -> Self::Result {
+        let mut legacy : BTreeMap<Uuid, String> = BTreeMap::new();
+        for row in self.store.q_once("[:find ?u ?secret
+                                       :where [?w :webhook/uuid ?u]
+                                              [?w :webhook/secret ?secret]]", None)
+                       .into_rel_result()? {
+            let mut row = row.into_iter();
+            if let Some(u) = uuid(row.next()) {
+                legacy.insert(u, text(row.next()));
+            }
+        }
+        Ok(self.store.q_once("[:find ?u ?url
+                               :where [?w :webhook/uuid ?u]
+                                      [?w :webhook/url ?url]]", None)
+            .into_rel_result()?
+            .into_iter()
+            .filter_map(|row| {
+                let mut row = row.into_iter();
+                uuid(row.next()).map(|u| Target{uuid: u, url: text(row.next()), legacy_secret: legacy.remove(&u)})
+            })
+            .collect())
+    }
+}
+
+/// Posts a signed delivery, answering the response status when one was
+/// received and the error when it was not a success.
+fn post(d: &Delivery, secret: &str) -> Box<Future<Item=(Option<u16>, Option<String>), Error=()>> {
+    match client::post(&d.url)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "Actix-web")
+            .header("X-Swapi-Delivery", d.uuid.hyphenated().to_string())
+            .header("X-Swapi-Signature", format!("sha256={}", sign(secret, d.payload.as_bytes())))
+            .body(d.payload.clone()) {
+        Ok(req) => Box::new(req.send()
+            .timeout(Duration::from_secs(DELIVERY_TIMEOUT_SECS))
+            .then(|res| Ok::<_, ()>(match res {
+                Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+                Ok(response) => (Some(response.status().as_u16()), Some(format!("status {}", response.status()))),
+                Err(e) => (None, Some(format!("{}", e))),
+            }))),
+        Err(e) => Box::new(::futures::future::ok((None, Some(format!("{}", e))))),
+    }
+}
+
+/// Posts signed planet events to the registered webhooks. Deliveries are
+/// recorded in the outbox before their first attempt, so the pending ones
+/// left by a previous run are picked up again with the due retries.
+pub struct WebhookDispatcher {
+    read: Addr<Syn, ReadExecutor>,
+    events: Addr<Syn, EventBroker>,
+    outbox: Outbox,
+    in_flight: HashSet<Uuid>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(read: Addr<Syn, ReadExecutor>, events: Addr<Syn, EventBroker>, outbox: Outbox) -> WebhookDispatcher {
+        WebhookDispatcher{read: read, events: events, outbox: outbox, in_flight: HashSet::new()}
+    }
+
+    /// Moves the secrets stored by earlier versions to the outbox.
+    fn adopt(&mut self, targets: &[Target]) {
+        for t in targets.iter() {
+            if let (None, Some(secret)) = (self.outbox.secret(t.uuid), t.legacy_secret.clone()) {
+                if let Err(e) = self.outbox.set_secret(t.uuid, secret) {
+                    warn!("got error keeping the secret of webhook {}: \t {}", t.uuid, e);
+                }
+            }
+        }
+    }
+
+    fn sweep(&mut self, ctx: &mut Context<Self>) {
+        for d in self.outbox.due(Utc::now()) {
+            self.deliver(d, ctx);
+        }
+    }
+
+    fn deliver(&mut self, d: Delivery, ctx: &mut Context<Self>) {
+        let secret = match self.outbox.secret(d.webhook) {
+            Some(secret) => secret.clone(),
+            None => return warn!("no secret for webhook {}, delivery {} left pending", d.webhook, d.uuid),
+        };
+        if !self.in_flight.insert(d.uuid) {
+            return;
+        }
+        let uuid = d.uuid;
+        debug!("delivering {} to {}, attempt {}", uuid, d.url, d.attempts + 1);
+        ctx.spawn(post(&d, &secret)
+            .into_actor(self)
+            .then(move |res, act, _| {
+                // released once recorded so a sweep in between does not post it twice
+                act.in_flight.remove(&uuid);
+                let (status, error) = res.unwrap_or((None, None));
+                if let Some(ref error) = error {
+                    warn!("delivery {} attempt failed: \t {}", uuid, error);
+                }
+                if let Err(e) = act.outbox.attempted(uuid, status, error) {
+                    warn!("got error recording delivery {}: \t {}", uuid, e);
+                }
+                fut::ok(())
+            }));
+    }
+}
+
+impl Actor for WebhookDispatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let addr : Addr<Syn, _> = ctx.address();
+        self.events.do_send(Watch(addr.recipient()));
+        ctx.spawn(self.read.send(WebhookTargets)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(Ok(targets)) => act.adopt(&targets),
+                    Ok(Err(e)) => warn!("got error reading webhooks: \t {}", e),
+                    Err(e) => warn!("got error reading webhooks: \t {}", e),
+                }
+                act.sweep(ctx);
+                fut::ok(())
+            }));
+        ctx.run_interval(Duration::from_secs(SWEEP_SECS), |act, ctx| act.sweep(ctx));
+    }
+}
+
+impl Handler<PlanetEvent> for WebhookDispatcher {
+    type Result = ();
+
+    #[cfg_attr(feature="flame_it", flame)]
+    fn handle(&mut self, msg: PlanetEvent, ctx: &mut Self::Context) -> Self::Result {
+        ctx.spawn(self.read.send(WebhookTargets)
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                let targets = match res {
+                    Ok(Ok(targets)) => targets,
+                    Ok(Err(e)) => { warn!("got error reading webhooks: \t {}", e); return fut::ok(()) }
+                    Err(e) => { warn!("got error reading webhooks: \t {}", e); return fut::ok(()) }
+                };
+                act.adopt(&targets);
+                for t in targets {
+                    let uuid = Uuid::new_v4();
+                    let payload = json!({
+                        "delivery": uuid,
+                        "event": msg.change.as_str(),
+                        "tx": msg.tx,
+                        "instant": msg.instant,
+                        "uuid": msg.uuid,
+                    }).to_string();
+                    match act.outbox.enqueue(t.uuid, t.url, msg.tx, uuid, payload) {
+                        Ok(d) => act.deliver(d, ctx),
+                        Err(e) => warn!("got error enqueuing delivery to {}: \t {}", t.uuid, e),
+                    }
+                }
+                fut::ok(())
+            }));
+    }
+}
+
+impl Handler<WebhookSecret> for WebhookDispatcher {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: WebhookSecret, _: &mut Self::Context) -> Self::Result {
+        self.outbox.set_secret(msg.webhook, msg.secret)
+    }
+}
+
+impl Handler<ForgetWebhook> for WebhookDispatcher {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: ForgetWebhook, _: &mut Self::Context) -> Self::Result {
+        self.outbox.forget(msg.0)
+    }
+}
+
+impl Handler<WebhookDeliveries> for WebhookDispatcher {
+    type Result = Result<Value>;
+
+    fn handle(&mut self, msg: WebhookDeliveries, _: &mut Self::Context) -> Self::Result {
+        match self.outbox.deliveries(msg.0) {
+            Some(deliveries) => Ok(json!(deliveries.into_iter().map(|d| json!({
+                "uuid": d.uuid,
+                "tx": d.tx,
+                "payload": d.payload,
+                "status": d.status,
+                "attempts": d.attempts,
+                "next_attempt": d.next_attempt,
+                "last_status": d.last_status,
+                "last_error": d.last_error,
+            })).collect::<Vec<Value>>())),
+            None => Err(Error::from_kind(ErrorKind::NotFound(format!("webhook not found: {}", msg.0)))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use outbox::Status;
+    use std::{env, fs};
+    use stub;
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(sign("Jefe", b"what do ya want for nothing?"),
+                   "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn failed_delivery_is_retried_then_recorded() {
+        let server = stub::serve(vec![stub::Reply::status(500), stub::Reply::status(200)]);
+        let path = env::temp_dir().join(format!("swapi-webhooks-{}.json", Uuid::new_v4().simple()));
+        let mut outbox = Outbox::open(&path);
+        let webhook = Uuid::new_v4();
+        outbox.set_secret(webhook, String::from("s3cr3t")).unwrap();
+        let payload = String::from(r#"{"event":"created"}"#);
+        let d = outbox.enqueue(webhook, format!("{}/hook", server.url), 1, Uuid::new_v4(), payload.clone()).unwrap();
+
+        let mut sys = System::new("webhooks");
+        let (status, error) = sys.run_until_complete(post(&d, "s3cr3t")).unwrap();
+        assert_eq!(status, Some(500));
+        let d = outbox.attempted(d.uuid, status, error).unwrap();
+        assert_eq!((d.status, d.attempts), (Status::Pending, 1));
+
+        let (status, error) = sys.run_until_complete(post(&d, "s3cr3t")).unwrap();
+        assert_eq!((status, error.clone()), (Some(200), None));
+        let d = outbox.attempted(d.uuid, status, error).unwrap();
+        assert_eq!((d.status, d.attempts, d.last_status), (Status::Delivered, 2, Some(200)));
+        assert_eq!(d.last_error.as_ref().map(|e| e.as_str()), Some("status 500 Internal Server Error"));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for r in requests.iter() {
+            assert_eq!(r.path, "/hook");
+            assert_eq!(r.header("x-swapi-signature"), Some(format!("sha256={}", sign("s3cr3t", payload.as_bytes()))));
+            assert_eq!(r.body, payload.as_bytes());
+        }
+        let _ = fs::remove_file(&path);
+    }
+}
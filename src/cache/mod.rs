@@ -0,0 +1,315 @@
+use chrono::{DateTime, Utc};
+use domain::{Films, EnrichmentStatus};
+use errors::{Result, Error, ErrorKind};
+use lru_time_cache::LruCache;
+use serde_json;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, atomic::{AtomicUsize, Ordering}};
+use std::time::Duration;
+
+pub mod redis;
+
+/// Films counts by planet name, shared by `FilmsExecutor` and
+/// the admin api. Backends expire entries past their `expires_at` on their own.
+pub trait FilmsCache: Send + Sync {
+    /// Where the counts are cached, reported in logs.
+    fn backend(&self) -> String;
+
+    fn get(&self, name: &str) -> Result<Option<Films>>;
+
+    fn insert(&self, name: String, films: Films) -> Result<()>;
+
+    fn len(&self) -> Result<usize>;
+
+    /// Every cached count, by planet name.
+    fn entries(&self) -> Result<Vec<(String, Films)>>;
+
+    /// Invalidates the count of a planet, false when it was not cached.
+    fn remove(&self, name: &str) -> Result<bool>;
+
+    /// Invalidates every count, answers how many were cached.
+    fn clear(&self) -> Result<usize>;
+
+    /// Entries dropped by the backend itself, on expiry or lack of room,
+    /// `None` when the backend does not tell.
+    fn evictions(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Saves the counts on shutdown, shared backends keep them on their own.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Films cache entry as persisted across restarts.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    name: String,
+    films: Films,
+}
+
+/// Entry of a saved films cache, in any format written so far.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Saved {
+    Entry(Entry),
+    /// Written before counts kept their status and expiry, a count fetched
+    /// then and cached for the default ttl.
+    Count{name: String, count: i64, fetched_at: DateTime<Utc>},
+}
+
+impl Saved {
+    fn entry(self, ttl: Duration) -> Entry {
+        match self {
+            Saved::Entry(e) => e,
+            Saved::Count{name, count, fetched_at} => {
+                let ttl = ::chrono::Duration::from_std(ttl).unwrap_or_else(|_| ::chrono::Duration::max_value());
+                Entry{name: name, films: Films{count: Some(count), status: EnrichmentStatus::Ok, fetched_at: fetched_at
+                    , expires_at: fetched_at.checked_add_signed(ttl).unwrap_or(fetched_at)}}
+            }
+        }
+    }
+}
+
+/// In process lru cache bounded to a number of entries, saved to a file on
+/// shutdown and reloaded on start.
+pub struct MemoryCache {
+    entries: Mutex<LruCache<String, Films>>,
+    capacity: usize,
+    path: PathBuf,
+    /// Entries evicted for room or dropped once expired.
+    evicted: AtomicUsize,
+}
+
+impl MemoryCache {
+    /// Cache of at most `capacity` entries persisted at `path`, reloaded
+    /// without the expired ones. A missing or unreadable file is an empty cache,
+    /// counts saved by earlier versions expire `ttl` after they were fetched.
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize, ttl: Duration) -> MemoryCache {
+        let path = path.as_ref();
+        let mut entries = LruCache::with_capacity(capacity);
+        if let Err(e) = load(path, &mut entries, ttl) {
+            warn!("could not load films cache from {}: \t {}", path.display(), e);
+        }
+        MemoryCache{entries: Mutex::new(entries), capacity: capacity, path: path.to_path_buf()
+            , evicted: AtomicUsize::new(0)}
+    }
+
+    fn lock(&self) -> Result<MutexGuard<LruCache<String, Films>>> {
+        self.entries.lock().map_err(|poisoned| Error::from_kind(ErrorKind::Poisoned(format!("{}", poisoned))))
+    }
+
+    /// Drops the expired entries, counted as evicted.
+    fn purge(&self, cache: &mut LruCache<String, Films>) {
+        let expired : Vec<String> = cache.iter()
+            .filter(|&(_, films)| films.expired())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired.iter() {
+            let _ = cache.remove(name);
+        }
+        self.evicted.fetch_add(expired.len(), Ordering::Relaxed);
+    }
+}
+
+impl FilmsCache for MemoryCache {
+    fn backend(&self) -> String {
+        String::from("memory")
+    }
+
+    /// Expired entries are dropped when read, counted as evicted.
+    fn get(&self, name: &str) -> Result<Option<Films>> {
+        let mut guard = self.lock()?;
+        let films = guard.get(name).cloned();
+        match films {
+            Some(ref films) if films.expired() => {
+                let _ = guard.remove(name);
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            films => Ok(films),
+        }
+    }
+
+    /// A new entry in a full cache evicts the least recently used one.
+    fn insert(&self, name: String, films: Films) -> Result<()> {
+        let mut guard = self.lock()?;
+        if !guard.contains_key(&name) && guard.len() >= self.capacity {
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = guard.insert(name, films);
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize> {
+        let mut guard = self.lock()?;
+        self.purge(&mut guard);
+        let len = guard.len();
+        Ok(len)
+    }
+
+    fn entries(&self) -> Result<Vec<(String, Films)>> {
+        let mut guard = self.lock()?;
+        self.purge(&mut guard);
+        let entries = guard.iter().map(|(name, films)| (name.clone(), films.clone())).collect();
+        Ok(entries)
+    }
+
+    fn remove(&self, name: &str) -> Result<bool> {
+        let removed = self.lock()?.remove(name).is_some();
+        Ok(removed)
+    }
+
+    fn clear(&self) -> Result<usize> {
+        let mut guard = self.lock()?;
+        self.purge(&mut guard);
+        let names : Vec<String> = guard.iter().map(|(name, _)| name.clone()).collect();
+        for name in names.iter() {
+            let _ = guard.remove(name);
+        }
+        Ok(names.len())
+    }
+
+    fn evictions(&self) -> Result<Option<u64>> {
+        Ok(Some(self.evicted.load(Ordering::Relaxed) as u64))
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut guard = self.lock()?;
+        self.purge(&mut guard);
+        save(&self.path, &mut guard).map(|_| ())
+    }
+}
+
+fn load(path: &Path, cache: &mut LruCache<String, Films>, ttl: Duration) -> Result<usize> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let saved : Vec<Saved> = serde_json::from_reader(File::open(path)?)?;
+    let mut loaded = 0;
+    for e in saved.into_iter().map(|s| s.entry(ttl)) {
+        if !e.films.expired() {
+            let _ = cache.insert(e.name, e.films);
+            loaded += 1;
+        }
+    }
+    info!("loaded {} films counts from {}", loaded, path.display());
+    Ok(loaded)
+}
+
+/// Writes the films cache to `path`, through a temporary file renamed over it
+/// so a crash never leaves a truncated cache behind.
+fn save(path: &Path, cache: &mut LruCache<String, Films>) -> Result<usize> {
+    let entries : Vec<Entry> = cache.iter()
+        .map(|(name, films)| Entry{name: name.clone(), films: films.clone()})
+        .collect();
+    let tmp = path.with_extension("json.tmp");
+    serde_json::to_writer(File::create(&tmp)?, &entries)?;
+    fs::rename(&tmp, path)?;
+    info!("saved {} films counts to {}", entries.len(), path.display());
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{env, thread};
+    use std::io::Write;
+    use uuid::Uuid;
+
+    fn path() -> PathBuf {
+        env::temp_dir().join(format!("swapi-cache-{}.json", Uuid::new_v4().simple()))
+    }
+
+    fn films(count: i64, ttl: Duration) -> Films {
+        Films::new(Some(count), EnrichmentStatus::Ok, ttl)
+    }
+
+    /// Checks the behavior every backend shares, on an empty cache.
+    pub fn exercise(cache: &FilmsCache) {
+        let minute = Duration::from_secs(60);
+        assert!(cache.get("hoth").unwrap().is_none());
+        cache.insert(String::from("hoth"), films(3, minute)).unwrap();
+        cache.insert(String::from("bespin"), films(1, minute)).unwrap();
+        assert_eq!(cache.get("hoth").unwrap().and_then(|f| f.count), Some(3));
+        assert_eq!(cache.len().unwrap(), 2);
+        let mut names : Vec<String> = cache.entries().unwrap().into_iter().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, vec!["bespin", "hoth"]);
+
+        cache.insert(String::from("hoth"), films(4, minute)).unwrap();
+        assert_eq!(cache.get("hoth").unwrap().and_then(|f| f.count), Some(4));
+        assert!(cache.get("hoth").unwrap().unwrap().ttl() <= minute);
+
+        assert!(cache.remove("hoth").unwrap());
+        assert!(!cache.remove("hoth").unwrap());
+        assert!(cache.get("hoth").unwrap().is_none());
+
+        cache.insert(String::from("endor"), films(1, minute)).unwrap();
+        assert_eq!(cache.clear().unwrap(), 2);
+        assert_eq!(cache.len().unwrap(), 0);
+
+        cache.insert(String::from("dagobah"), films(2, Duration::from_secs(1))).unwrap();
+        assert!(cache.get("dagobah").unwrap().is_some());
+        thread::sleep(Duration::from_millis(1500));
+        assert_eq!(cache.len().unwrap(), 0);
+        assert!(cache.entries().unwrap().is_empty());
+        assert!(cache.get("dagobah").unwrap().is_none());
+    }
+
+    #[test]
+    fn counts_capacity_evictions_and_expiry_drops() {
+        let cache = MemoryCache::open(path(), 2, Duration::from_secs(60));
+        let minute = Duration::from_secs(60);
+        cache.insert(String::from("hoth"), films(1, minute)).unwrap();
+        cache.insert(String::from("bespin"), films(1, minute)).unwrap();
+        cache.insert(String::from("hoth"), films(2, minute)).unwrap();
+        cache.remove("bespin").unwrap();
+        assert_eq!(cache.evictions().unwrap(), Some(0));
+
+        cache.insert(String::from("endor"), films(1, minute)).unwrap();
+        cache.insert(String::from("dagobah"), films(1, Duration::from_secs(0))).unwrap();
+        assert_eq!(cache.evictions().unwrap(), Some(1));
+        assert!(cache.get("dagobah").unwrap().is_none());
+        assert_eq!(cache.evictions().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn memory_cache() {
+        exercise(&MemoryCache::open(path(), 10, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn saves_live_counts_only() {
+        let path = path();
+        let cache = MemoryCache::open(&path, 10, Duration::from_secs(60));
+        cache.insert(String::from("hoth"), films(3, Duration::from_secs(60))).unwrap();
+        cache.insert(String::from("endor"), films(1, Duration::from_secs(0))).unwrap();
+        cache.flush().unwrap();
+
+        let reloaded = MemoryCache::open(&path, 10, Duration::from_secs(60));
+        let entries = reloaded.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].0.as_str(), entries[0].1.count), ("hoth", Some(3)));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn loads_counts_saved_by_earlier_versions() {
+        let path = path();
+        let fetched = Utc::now() - ::chrono::Duration::seconds(30);
+        let old = Utc::now() - ::chrono::Duration::seconds(120);
+        write!(File::create(&path).unwrap(), r#"[{{"name":"hoth","count":3,"fetched_at":"{}"}},
+                                                 {{"name":"endor","count":1,"fetched_at":"{}"}}]"#,
+               fetched.to_rfc3339(), old.to_rfc3339()).unwrap();
+
+        let cache = MemoryCache::open(&path, 10, Duration::from_secs(60));
+        let hoth = cache.get("hoth").unwrap().unwrap();
+        assert_eq!((hoth.count, hoth.status), (Some(3), EnrichmentStatus::Ok));
+        assert_eq!(hoth.expires_at, fetched + ::chrono::Duration::seconds(60));
+        assert!(cache.get("endor").unwrap().is_none());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,139 @@
+use ::redis::{self, Client, Commands, Connection, RedisResult};
+use domain::Films;
+use errors::{Result, Error, ErrorKind};
+use serde_json;
+use std::cmp;
+use std::sync::Mutex;
+use super::FilmsCache;
+
+/// Prefix of the films cache keys, so the cache shares a redis database.
+const PREFIX: &'static str = "swapi:films:";
+
+/// Keys fetched or deleted per round trip.
+const BATCH: usize = 100;
+
+/// Cache kept in redis and shared by every swapi instance pointed at it,
+/// entries expire with redis key ttls. Unbounded, redis evicts keys past its
+/// own `maxmemory`.
+pub struct RedisCache {
+    url: String,
+    client: Client,
+    /// Dropped once broken, reconnected on the next call.
+    con: Mutex<Option<Connection>>,
+}
+
+impl RedisCache {
+    pub fn open(url: &str) -> Result<RedisCache> {
+        let client = Client::open(url)?;
+        let con = client.get_connection()?;
+        Ok(RedisCache{url: String::from(url), client: client, con: Mutex::new(Some(con))})
+    }
+
+    /// Runs `f` on the connection, reconnecting and trying once more when
+    /// it was lost.
+    fn with<T, F>(&self, f: F) -> Result<T> where
+        F: Fn(&Connection) -> RedisResult<T>
+    {
+        let mut con = self.con.lock().map_err(|poisoned| Error::from_kind(ErrorKind::Poisoned(format!("{}", poisoned))))?;
+        let mut retried = false;
+        loop {
+            if con.is_none() {
+                *con = Some(self.client.get_connection()?);
+            }
+            let res = f(con.as_ref().unwrap());
+            match res {
+                Ok(out) => return Ok(out),
+                Err(e) => {
+                    if e.kind() != redis::ErrorKind::IoError {
+                        return Err(Error::from(e));
+                    }
+                    warn!("lost redis connection to {}: \t {}", self.url, e);
+                    *con = None;
+                    if retried {
+                        return Err(Error::from(e));
+                    }
+                    retried = true;
+                }
+            }
+        }
+    }
+
+    /// Keys of the cache, walked with `SCAN` so redis is never blocked.
+    fn keys(&self) -> Result<Vec<String>> {
+        self.with(|con| con.scan_match(format!("{}*", PREFIX)).map(|keys| keys.collect()))
+    }
+}
+
+fn key(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+impl FilmsCache for RedisCache {
+    fn backend(&self) -> String {
+        self.url.clone()
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Films>> {
+        let value : Option<String> = self.with(|con| con.get(key(name)))?;
+        match value {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, name: String, films: Films) -> Result<()> {
+        let json = serde_json::to_string(&films)?;
+        let ttl = cmp::max(films.ttl().as_secs(), 1) as usize;
+        self.with(|con| con.set_ex(key(&name), json.as_str(), ttl))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.keys()?.len())
+    }
+
+    fn entries(&self) -> Result<Vec<(String, Films)>> {
+        let mut entries = Vec::new();
+        for keys in self.keys()?.chunks(BATCH) {
+            let values : Vec<Option<String>> = self.with(|con| redis::cmd("MGET").arg(keys.to_vec()).query(con))?;
+            // keys expired since the scan answer nothing
+            for (k, value) in keys.iter().zip(values) {
+                if let Some(json) = value {
+                    entries.push((String::from(&k[PREFIX.len()..]), serde_json::from_str(&json)?));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn remove(&self, name: &str) -> Result<bool> {
+        let removed : usize = self.with(|con| con.del(key(name)))?;
+        Ok(removed > 0)
+    }
+
+    fn clear(&self) -> Result<usize> {
+        let mut cleared = 0;
+        for keys in self.keys()?.chunks(BATCH) {
+            let n : usize = self.with(|con| con.del(keys.to_vec()))?;
+            cleared += n;
+        }
+        Ok(cleared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::tests::exercise;
+    use std::env;
+
+    /// Needs a redis, run with `cargo test -- --ignored`. Database 15 of a
+    /// local redis unless `REDIS_URL` tells otherwise.
+    #[test]
+    #[ignore]
+    fn behaves_like_the_memory_cache() {
+        let url = env::var("REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1/15"));
+        let cache = RedisCache::open(&url).unwrap();
+        cache.clear().unwrap();
+        exercise(&cache);
+    }
+}
@@ -6,14 +6,21 @@ use env_logger::Builder;
 use std::env;
 use handlers;
 use pikkr::Pikkr;
-use actors::{WriterExecutor,State, InboundCacheProcessor, ReadExecutor, FilmsExecutor};
+use actors::{WriterExecutor,State, InboundCacheProcessor, ReadExecutor, FilmsExecutor, events::EventBroker
+    , webhooks::WebhookDispatcher, refresher::FilmsRefresher, cache::CacheExecutor};
 use actix::{SyncArbiter,Actor, Syn, Addr};
 use actix_web::{middleware, http, server, App, http::header, middleware::cors::Cors};
-use lru_time_cache::LruCache;
-use std::sync::{Mutex, Arc};
-use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use dirs::Directories;
+use outbox::Outbox;
 use mentat::Store;
+use schema;
+use cache::{FilmsCache, MemoryCache, redis::RedisCache};
+use upstream::{FilmsProvider, HttpProvider, StaticProvider, dump, resilience::{Breaker, Policy, ResilientProvider}};
+use domain::resources::{Resource, Person, Film, Starship, Vehicle, Species};
+use actix_web::middleware::cors::CorsBuilder;
+use handlers::resources;
 
 fn init_logger(pattern: &str) {
     // Always print backtrace on panic.
@@ -55,6 +62,33 @@ fn interface(interface: &str) -> String {
     .into()
 }
 
+/// Registers the CRSD routes of a resource under `/sw/<path>`.
+trait ResourceRoutes {
+    fn configure_resource<T: Resource>(&mut self) -> &mut Self;
+}
+
+impl ResourceRoutes for CorsBuilder<State> {
+    fn configure_resource<T: Resource>(&mut self) -> &mut Self {
+        let path = format!("/sw/{}", T::PATH);
+        self.resource(&path, |r| {
+                r.method(http::Method::POST).f(resources::create::<T>);
+                r.method(http::Method::GET).f(resources::read::<T>);
+            })
+            .resource(&format!("{}/", path), |r| r.method(http::Method::GET).f(resources::search::<T>))
+            .resource(&format!("{}/{{uuid}}", path), |r| {
+                r.method(http::Method::DELETE).f(resources::delete::<T>);
+                r.method(http::Method::GET).f(resources::id::<T>);
+            })
+            .resource(&format!("{}/{{uuid}}/{{relation}}", path), |r| {
+                r.method(http::Method::GET).f(resources::related::<T>);
+            })
+            .resource(&format!("{}/{{uuid}}/{{relation}}/{{target}}", path), |r| {
+                r.method(http::Method::PUT).f(resources::link::<T>);
+                r.method(http::Method::DELETE).f(resources::unlink::<T>);
+            })
+    }
+}
+
 pub fn run<I, T>(args: I) -> Result<()> where
 	I: IntoIterator<Item = T>,
 	T: Into<::std::ffi::OsString> + Clone,
@@ -67,103 +101,170 @@ pub fn run<I, T>(args: I) -> Result<()> where
     let port = value_t!(matches, "port", u16).unwrap_or(get_api_port());
     let inet = interface(matches.value_of("interface").unwrap());
     let db = matches.value_of("db");
-    let expire = value_t!(matches, "expire", u64).unwrap();    
-
-    let mut dirs = Directories::default();
-    if let Some(dbpath) = db {
-        dirs.db = String::from(dbpath);
+    let admin_token = matches.value_of("admin-token").map(String::from);
+    let expire = value_t!(matches, "expire", u64)?;
+    let negative_ttl = Duration::from_secs(value_t!(matches, "negative-ttl", u64)?);
+    let capacity = value_t!(matches, "capacity", usize)?;
+    let stale = Duration::from_secs(value_t!(matches, "stale", u64)?);
+    let refresh = value_t!(matches, "refresh", u64)?;
+    let policy = Policy{
+        timeout: Duration::from_secs(value_t!(matches, "timeout", u64)?),
+        retries: value_t!(matches, "retries", u32)?,
+        threshold: value_t!(matches, "breaker", u32)?,
+        cooldown: Duration::from_secs(value_t!(matches, "cooldown", u64)?),
+        lookup: Duration::from_secs(value_t!(matches, "lookup-timeout", u64)?),
+    };
+    if policy.lookup < policy.deadline() {
+        warn!("films lookups time out after {}s, before a single upstream request and its retries may", policy.lookup.as_secs());
     }
+
+    let dirs = match db {
+        Some(dbpath) => Directories::with_db(dbpath),
+        None => Directories::default(),
+    };
     let _ = dirs.create_dirs(); 
 
     let sys = System::new("swapi");    
 
-    if !Path::new(&dirs.db).exists() {   
+    {
         let mut store = Store::open(&dirs.db).expect("open must not fail!");
-        let t = "[
-                  {:db/ident :planet/uuid    
-                   :db/valueType :db.type/uuid  
-                   :db/index true   
-                   :db/unique :db.unique/identity                   
-                   :db/cardinality :db.cardinality/one}            
-                  {:db/ident :planet/name
-                   :db/valueType :db.type/string
-                   :db/index true 
-                   :db/unique :db.unique/value   
-                   :db/fulltext true                
-                   :db/cardinality :db.cardinality/one}
-                  {:db/ident :planet/climate
-                   :db/valueType :db.type/string
-                   :db/cardinality :db.cardinality/one}
-                  {:db/ident :planet/terrain
-                   :db/valueType :db.type/string
-                   :db/cardinality :db.cardinality/one}                                   
-                 ]";
-
-                 
-        let _  = store.transact(t).unwrap();
+        schema::ensure(&mut store)?;
     }
 
     let time_to_live = ::std::time::Duration::from_secs(expire); // default 7 days
-    let lru_cache = Arc::new(Mutex::new(LruCache::<String, i64>::with_expiry_duration(time_to_live)));
-    let ccache = lru_cache.clone();
+    let films_cache : Arc<FilmsCache> = match matches.value_of("redis") {
+        Some(url) => Arc::new(RedisCache::open(url)?),
+        None => Arc::new(MemoryCache::open(&dirs.cache, capacity, time_to_live)),
+    };
+    info!("caching films in {}", films_cache.backend());
+    let ccache = films_cache.clone();
     
     let d = dirs.db.clone();
     let dr = dirs.db.clone();
     let queries = vec![
-            "$.count".as_bytes(),
-            "$.results.films".as_bytes(),
+            "$.next".as_bytes(),
+            "$.results".as_bytes(),
     ];         
 
+    let events_addr : Addr<Syn, _> = EventBroker::default().start();
+    let events_addr2 = events_addr.clone();
+
     // Start db executor actors 
     let db_addr = SyncArbiter::start(1, move || {
         let store = Store::open(&d).expect("open store must not fail!");       
-        WriterExecutor{store}
+        WriterExecutor{store: store, events: events_addr2.clone()}
     });   
 
-    let ccache3 = ccache.clone();
     let proc_addr = SyncArbiter::start(8, move || {
         let pikkr = Pikkr::new(&queries.clone(), 1).unwrap();         
-        InboundCacheProcessor{pikkr: pikkr, cache: ccache3.clone()}
+        InboundCacheProcessor{pikkr: pikkr}
     });   
 
-    let ccache2 = ccache.clone();
+    // only the upstream api is retried behind the breaker, local files can't recover
+    let breaker = Arc::new(Breaker::new(policy));
+    let provider : Arc<FilmsProvider> = match (matches.value_of("offline"), matches.value_of("films")) {
+        (Some(dir), _) => Arc::new(dump::open(dir)?),
+        (None, Some(path)) => Arc::new(StaticProvider::open(path)?),
+        (None, None) => Arc::new(ResilientProvider::new(
+            Arc::new(HttpProvider::new(matches.value_of("upstream").unwrap(), policy.timeout)), breaker.clone())),
+    };
+    info!("counting films from {}", provider.source());
+
+    // a single thread, so the films cache is read and written in order
+    let cache_addr = SyncArbiter::start(1, move || {
+        CacheExecutor{cache: ccache.clone(), stale_after: stale}
+    });
+
+    let cache_addr2 = cache_addr.clone();
     let proc_addr2 = proc_addr.clone();
     let film_addr : Addr<Syn, _> = FilmsExecutor::create(move |ctx| {  
         ctx.set_mailbox_capacity(1000);
-        FilmsExecutor{processor: Some(proc_addr2.clone()), cache: Some(ccache2.clone())}
+        FilmsExecutor{provider: Some(provider.clone()), processor: Some(proc_addr2.clone()), cache: Some(cache_addr2.clone())
+            , stale_after: stale, ttl: time_to_live, negative_ttl: negative_ttl, lookup_timeout: policy.lookup
+            , ..Default::default()}
     });          
 
+    let film_addr2 = film_addr.clone();
     let read_addr = SyncArbiter::start(8, move || {
         let store = Store::open(&dr).expect("open store must not fail!");       
-        ReadExecutor{store: store, films: film_addr.clone()}
+        ReadExecutor{store: store, films: film_addr2.clone(), films_wait: policy.lookup}
     });      
 
+    let webhooks_addr : Addr<Syn, _> = WebhookDispatcher::new(read_addr.clone(), events_addr.clone()
+        , Outbox::open(&dirs.webhooks)).start();
+    let every = if refresh > 0 { Some(Duration::from_secs(refresh)) } else { None };
+    let refresher_addr : Addr<Syn, _> = FilmsRefresher::new(read_addr.clone(), film_addr.clone(), every).start();
+
     server::new(move || {            
-        App::with_state(State{db: db_addr.clone(), processor: proc_addr.clone(), read: read_addr.clone(), cache: lru_cache.clone()})
+        App::with_state(State{db: db_addr.clone(), read: read_addr.clone(), films: film_addr.clone()
+            , events: events_addr.clone(), webhooks: webhooks_addr.clone()
+            , refresher: refresher_addr.clone(), cache: cache_addr.clone(), upstream: breaker.clone()
+            , admin_token: admin_token.clone()})
             // enable logger
             .middleware(middleware::Logger::default())
             .configure(|app| Cors::for_app(app)
-                .allowed_methods(vec!["GET", "POST", "DELETE"])
+                .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
                 .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
                 .allowed_header(header::CONTENT_TYPE)
                 .max_age(3600)            
+                .configure_resource::<Person>()
+                .configure_resource::<Film>()
+                .configure_resource::<Starship>()
+                .configure_resource::<Vehicle>()
+                .configure_resource::<Species>()
                 .resource("/sw", |r| {
                     r.method(http::Method::POST).f(handlers::create);
                     r.method(http::Method::GET).f(handlers::read);
                 }) 
+                .resource("/sw/webhooks", |r| {
+                    r.method(http::Method::POST).f(handlers::webhooks::create);
+                    r.method(http::Method::GET).f(handlers::webhooks::read);
+                })
+                .resource("/sw/webhooks/{uuid}", |r| r.method(http::Method::DELETE).f(handlers::webhooks::delete))
+                .resource("/sw/webhooks/{uuid}/deliveries", |r| r.method(http::Method::GET).f(handlers::webhooks::deliveries))
+                .resource("/sw/ws", |r| r.method(http::Method::GET).f(handlers::socket))
+                .resource("/sw/events", |r| r.method(http::Method::GET).f(handlers::events))
+                .resource("/sw/changes", |r| r.method(http::Method::GET).f(handlers::changes))
+                .resource("/sw/deleted", |r| r.method(http::Method::GET).f(handlers::deleted))
                 .resource("/sw/{uuid}", |r| { 
                     r.method(http::Method::DELETE).f(handlers::delete);
                     r.method(http::Method::GET).f(handlers::id);
+                    r.method(http::Method::PUT).f(handlers::update);
+                    r.method(http::Method::PATCH).f(handlers::patch);
+                })
+                .resource("/sw/{uuid}/history", |r| r.method(http::Method::GET).f(handlers::history))
+                .resource("/sw/{uuid}/restore", |r| r.method(http::Method::POST).f(handlers::restore))
+                .resource("/sw/{uuid}/{relation}", |r| r.method(http::Method::GET).f(handlers::related))
+                .resource("/sw/{uuid}/{relation}/{target}", |r| {
+                    r.method(http::Method::PUT).f(handlers::link);
+                    r.method(http::Method::DELETE).f(handlers::unlink);
                 })
-                .resource("/sw/", |r| r.method(http::Method::GET).with2(handlers::search))
+                .resource("/sw/", |r| r.method(http::Method::GET).f(handlers::search))
+                .resource("/admin/upstream", |r| r.method(http::Method::GET).f(handlers::admin::upstream))
+                .resource("/admin/cache", |r| {
+                    r.method(http::Method::GET).f(handlers::admin::stats);
+                    r.method(http::Method::DELETE).f(handlers::admin::clear);
+                })
+                .resource("/admin/cache/entries", |r| r.method(http::Method::GET).f(handlers::admin::entries))
+                .resource("/admin/cache/entries/{name}", |r| {
+                    r.method(http::Method::GET).f(handlers::admin::entry);
+                    r.method(http::Method::DELETE).f(handlers::admin::invalidate);
+                })
+                .resource("/admin/cache/warm", |r| r.method(http::Method::POST).f(handlers::admin::warm))
                 .resource("/", |r| r.method(http::Method::GET).f(handlers::redirect))
                 .register())
         })
         .bind((inet.as_str(), port)).unwrap()
         .shutdown_timeout(1)
+        .system_exit()
         .start();        
 
     let _ = sys.run();
 
+    // flushed once the server stopped, so the next start is warm
+    if let Err(e) = films_cache.flush() {
+        warn!("could not save films cache: \t {}", e);
+    }
+
 	Ok(())    
 }
\ No newline at end of file
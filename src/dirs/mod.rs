@@ -10,6 +10,8 @@ const PRODUCT: &'static str = "swapi";
 pub struct Directories {
     pub base: String,
     pub db: String,
+    pub cache: String,
+    pub webhooks: String,
 }
 
 impl Default for Directories {
@@ -18,12 +20,30 @@ impl Default for Directories {
         let base = replace_home(&data_dir, "$BASE");
         Directories {
             db: db_root_path(&base).into_string().unwrap(),
+            cache: cache_file_path(&base).into_string().unwrap(),
+            webhooks: webhooks_file_path(&base).into_string().unwrap(),
             base: base,            
         }
     }
 }
 
 impl Directories {
+    /// Directories of the database at `db`, with the films cache and webhooks
+    /// files beside it, named after it so instances on other databases of the
+    /// same directory don't share them.
+    pub fn with_db(db: &str) -> Directories {
+        let base = match Path::new(db).parent() {
+            Some(parent) if parent.as_os_str().len() > 0 => parent.to_string_lossy().into_owned(),
+            _ => String::from("."),
+        };
+        Directories {
+            db: String::from(db),
+            cache: format!("{}.films-cache.json", db),
+            webhooks: format!("{}.webhooks.json", db),
+            base: base,
+        }
+    }
+
     pub fn create_dirs(&self)
                        -> Result<(), String> {
         fs::create_dir_all(&self.base)
@@ -55,3 +75,29 @@ pub fn db_root_path(base: &str) -> OsString {
     dir.push("db");
     dir.into_os_string()
 }
+
+pub fn cache_file_path(base: &str) -> OsString {
+    let mut file = Path::new(base).to_path_buf();
+    file.push("films-cache.json");
+    file.into_os_string()
+}
+
+pub fn webhooks_file_path(base: &str) -> OsString {
+    let mut file = Path::new(base).to_path_buf();
+    file.push("webhooks.json");
+    file.into_os_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_follow_the_db() {
+        let dirs = Directories::with_db("/srv/swapi/prod.db");
+        assert_eq!(dirs, Directories{base: String::from("/srv/swapi"), db: String::from("/srv/swapi/prod.db")
+            , cache: String::from("/srv/swapi/prod.db.films-cache.json")
+            , webhooks: String::from("/srv/swapi/prod.db.webhooks.json")});
+        assert_eq!(Directories::with_db("local.db").base, ".");
+    }
+}
@@ -0,0 +1,53 @@
+use actix::Message;
+use errors::Result;
+use serde_json::value::Value;
+use super::Films;
+
+/// Cached films of a planet not expired yet, by films key.
+pub struct GetFilms(pub String);
+
+impl Message for GetFilms {
+    type Result = Result<Option<Films>>;
+}
+
+/// Caches the outcome of a lookup, unless it failed and a count is cached.
+pub struct PutFilms(pub String, pub Films);
+
+impl Message for PutFilms {
+    type Result = ();
+}
+
+/// Films cache backend, size and evictions.
+pub struct CacheStats;
+
+impl Message for CacheStats {
+    type Result = Result<Value>;
+}
+
+/// Films lookups answered from the cache, fresh or stale, and from upstream.
+pub struct LookupStats;
+
+impl Message for LookupStats {
+    type Result = Result<Value>;
+}
+
+/// Every cached films count.
+pub struct CacheEntries;
+
+impl Message for CacheEntries {
+    type Result = Result<Value>;
+}
+
+/// Cached films count of a planet, by name.
+pub struct CacheEntry(pub String);
+
+impl Message for CacheEntry {
+    type Result = Result<Value>;
+}
+
+/// Invalidates the cached films count of a planet, or of every planet.
+pub struct InvalidateFilms(pub Option<String>);
+
+impl Message for InvalidateFilms {
+    type Result = Result<usize>;
+}
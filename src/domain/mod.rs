@@ -1,9 +1,20 @@
-use actix::{Message,Syn,dev::Request};
+use actix::{Message,Syn,Recipient,dev::Request};
 use errors::Result;
 use mentat::TxReport;
 use serde_json::value::Value;
 use crossbeam_channel::Receiver;
 use uuid::Uuid;
+use txlog::{AsOf, Change, Event};
+use chrono::{DateTime, Utc};
+use futures::sync::mpsc::UnboundedSender;
+use std::time::Duration;
+use unicode_normalization::UnicodeNormalization;
+use caseless::default_case_fold_str;
+use std::cmp::Ordering;
+
+pub mod resources;
+pub mod webhooks;
+pub mod cache;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Planet {
@@ -20,26 +31,244 @@ pub struct InnerPlanet {
     pub request: Option<Request<Syn, ::actors::FilmsExecutor, ReadFilms>>,
 }
 
+/// Outcome of a planet creation, with the uuid resolved from the transaction tempid.
+pub struct Created {
+    pub report: TxReport,
+    pub uuid: Uuid,
+}
+
 impl Message for Planet {
-    type Result = Result<TxReport>;
+    type Result = Result<Created>;
 }
 
 pub struct SearchResponse(pub String, pub Vec<u8>);
 
+/// Outcome of one page of an upstream planets search: the films count of the
+/// planet named exactly as searched, or the page to look for it next.
+#[derive(Debug)]
+pub enum FilmsPage {
+    Found(i64),
+    Next(String),
+}
+
 impl Message for SearchResponse {
-    type Result = Result<i64>;
+    type Result = Result<FilmsPage>;
 }
 
-pub struct ReadPlanets;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanetSort {
+    Name,
+    NameDesc,
+    Climate,
+}
+
+impl PlanetSort {
+    pub fn parse(s: &str) -> Option<PlanetSort> {
+        match s {
+            "name" => Some(PlanetSort::Name),
+            "-name" => Some(PlanetSort::NameDesc),
+            "climate" => Some(PlanetSort::Climate),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PlanetSort::Name => "name",
+            PlanetSort::NameDesc => "-name",
+            PlanetSort::Climate => "climate",
+        }
+    }
+
+    /// Position of a planet in this sort, names are unique so they settle ties on climate.
+    pub fn keyset(&self, uuid: &str, name: &str, climate: &str) -> Keyset {
+        let keys = match *self {
+            PlanetSort::Name | PlanetSort::NameDesc => vec![String::from(name)],
+            PlanetSort::Climate => vec![String::from(climate), String::from(name)],
+        };
+        Keyset{keys: keys, uuid: String::from(uuid), before: false}
+    }
+
+    /// Number of sort keys a keyset of this sort carries.
+    pub fn arity(&self) -> usize {
+        match *self {
+            PlanetSort::Name | PlanetSort::NameDesc => 1,
+            PlanetSort::Climate => 2,
+        }
+    }
+
+    pub fn compare(&self, a: &Keyset, b: &Keyset) -> Ordering {
+        let order = a.keys.cmp(&b.keys).then_with(|| a.uuid.cmp(&b.uuid));
+        match *self {
+            PlanetSort::NameDesc => order.reverse(),
+            _ => order,
+        }
+    }
+}
+
+/// Page boundary, the sort keys and uuid of the row a page starts after,
+/// or ends before when `before` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyset {
+    pub keys: Vec<String>,
+    pub uuid: String,
+    #[serde(default)]
+    pub before: bool,
+}
+
+impl Default for PlanetSort {
+    fn default() -> PlanetSort {
+        PlanetSort::Name
+    }
+}
+
+/// One page of the planets collection, only the page entries are enriched with films.
+/// Past states read with `as_of` are served from the transaction log without films.
+/// A page starts at `offset` unless it is positioned by a keyset.
+pub struct ReadPlanets {
+    pub offset: usize,
+    pub limit: usize,
+    pub sort: PlanetSort,
+    pub keyset: Option<Keyset>,
+    pub as_of: Option<AsOf>,
+}
+
+pub struct Page {
+    pub count: usize,
+    pub results: Vec<Value>,
+    pub next: Option<Keyset>,
+    pub previous: Option<Keyset>,
+    pub as_of: Option<i64>,
+}
 
 impl Message for ReadPlanets {
-    type Result = Result<Value>;
+    type Result = Result<Page>;
 }
 
 pub struct ReadFilms(pub String);
 
 impl Message for ReadFilms {
-    type Result = Result<Receiver<Result<i64>>>;
+    type Result = Result<Receiver<FilmsCount>>;
+}
+
+/// Re-fetches the films of a planet in background unless its cached count is fresh.
+pub struct RefreshFilms(pub String);
+
+impl Message for RefreshFilms {
+    type Result = ();
+}
+
+/// Queues the films of planets for a refresh ahead of the periodic ones,
+/// sent by `FilmsRefresher` one at a time without waiting for the interval.
+pub struct WarmPlanets(pub Vec<String>);
+
+impl Message for WarmPlanets {
+    type Result = ();
+}
+
+/// Names of every planet in the store.
+pub struct PlanetNames;
+
+impl Message for PlanetNames {
+    type Result = Result<Vec<String>>;
+}
+
+/// Outcome of the upstream lookup of a planet films, cached and answered to
+/// every `ReadFilms` waiting on it.
+pub struct FilmsFetched(pub String, pub Films);
+
+impl Message for FilmsFetched {
+    type Result = ();
+}
+
+/// Canonical form of a planet name the films cache is keyed by, so names
+/// differing only in case, surrounding blanks or unicode composition share
+/// an entry and a lookup. Case folded rather than lowercased, `ß` and `SS`
+/// fold alike, and normalized again as folding may decompose.
+pub fn films_key(name: &str) -> String {
+    default_case_fold_str(name.nfkc().collect::<String>().trim()).nfkc().collect()
+}
+
+/// Films count of a planet as looked up upstream, what the films cache keeps.
+/// Failed lookups are kept too, for a shorter time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Films {
+    /// Unknown unless the lookup status is ok.
+    pub count: Option<i64>,
+    pub status: EnrichmentStatus,
+    pub fetched_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Films {
+    /// Outcome of a lookup made now, kept for `ttl`.
+    pub fn new(count: Option<i64>, status: EnrichmentStatus, ttl: Duration) -> Films {
+        let now = Utc::now();
+        let ttl = ::chrono::Duration::from_std(ttl).unwrap_or_else(|_| ::chrono::Duration::max_value());
+        Films{count: count, status: status, fetched_at: now
+            , expires_at: now.checked_add_signed(ttl).unwrap_or(now)}
+    }
+
+    /// Whether the count was fetched less than `ttl` ago.
+    pub fn fresh(&self, ttl: Duration) -> bool {
+        match ::chrono::Duration::from_std(ttl) {
+            Ok(ttl) => Utc::now().signed_duration_since(self.fetched_at) < ttl,
+            Err(_) => true,
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+
+    /// Time left before the entry expires.
+    pub fn ttl(&self) -> Duration {
+        self.expires_at.signed_duration_since(Utc::now()).to_std().unwrap_or(Duration::from_secs(0))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EnrichmentStatus {
+    Ok,
+    Stale,
+    Unavailable,
+    NotFound,
+}
+
+/// How the films count of a planet was resolved, so clients tell a real count
+/// from a failed lookup.
+#[derive(Serialize, Debug, Clone)]
+pub struct Enrichment {
+    pub status: EnrichmentStatus,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub source: Option<String>,
+}
+
+/// Films count of a planet, unknown unless the enrichment status is ok.
+#[derive(Debug, Clone)]
+pub struct FilmsCount {
+    pub count: Option<i64>,
+    pub enrichment: Enrichment,
+}
+
+impl FilmsCount {
+    pub fn of(films: Films, source: String) -> FilmsCount {
+        FilmsCount{count: films.count, enrichment: Enrichment{status: films.status
+            , fetched_at: Some(films.fetched_at), source: Some(source)}}
+    }
+
+    /// Count past its soft ttl, served while it is refreshed.
+    pub fn stale(films: Films, source: String) -> FilmsCount {
+        let mut count = FilmsCount::of(films, source);
+        count.enrichment.status = EnrichmentStatus::Stale;
+        count
+    }
+
+    pub fn unavailable(source: Option<String>) -> FilmsCount {
+        FilmsCount{count: None, enrichment: Enrichment{status: EnrichmentStatus::Unavailable
+            , fetched_at: None, source: source}}
+    }
 }
 
 pub struct DeletePlanet(pub Uuid);
@@ -48,16 +277,129 @@ impl Message for DeletePlanet {
     type Result = Result<TxReport>;
 }
 
-pub struct GetPlanet(pub Uuid);
+pub struct UpdatePlanet(pub Uuid, pub Planet);
+
+impl Message for UpdatePlanet {
+    type Result = Result<TxReport>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PlanetPatch {
+    pub name: Option<String>,
+    pub climate: Option<String>,
+    pub terrain: Option<String>,
+}
+
+pub struct PatchPlanet(pub Uuid, pub PlanetPatch);
+
+impl Message for PatchPlanet {
+    type Result = Result<TxReport>;
+}
+
+/// Planet by uuid with its relations expanded along the given paths.
+pub struct GetPlanet(pub Uuid, pub Vec<String>);
 
 impl Message for GetPlanet {
     type Result = Result<Value>;
 }
 
+/// Planet as it was at a past transaction.
+pub struct GetPlanetAsOf(pub Uuid, pub AsOf);
+
+impl Message for GetPlanetAsOf {
+    type Result = Result<Value>;
+}
+
+/// Every transaction that touched a planet, deleted ones included.
+pub struct PlanetHistory(pub Uuid);
+
+impl Message for PlanetHistory {
+    type Result = Result<Value>;
+}
+
+/// Re-asserts the values a planet had before its deletion.
+pub struct RestorePlanet(pub Uuid);
+
+impl Message for RestorePlanet {
+    type Result = Result<TxReport>;
+}
+
+/// Deleted planets, latest deletion first, at most `limit`.
+pub struct DeletedPlanets(pub usize);
+
+impl Message for DeletedPlanets {
+    type Result = Result<Value>;
+}
+
+/// Planets created, updated and deleted after a transaction.
+pub struct PlanetChanges(pub i64);
+
+impl Message for PlanetChanges {
+    type Result = Result<Value>;
+}
+
+/// A create, update or delete committed on a planet.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanetEvent {
+    pub tx: i64,
+    pub instant: DateTime<Utc>,
+    pub uuid: Uuid,
+    pub change: Change,
+    /// Planet name after the change, when known to the writer.
+    #[serde(skip)]
+    pub name: Option<String>,
+}
+
+impl Message for PlanetEvent {
+    type Result = ();
+}
+
+impl From<Event> for PlanetEvent {
+    fn from(e: Event) -> PlanetEvent {
+        PlanetEvent{tx: e.tx, instant: e.instant, uuid: e.uuid, change: e.change, name: None}
+    }
+}
+
+/// Registers a channel receiving every planet event from now on.
+pub struct Subscribe(pub UnboundedSender<PlanetEvent>);
+
+impl Message for Subscribe {
+    type Result = ();
+}
+
+/// Registers an actor receiving every planet event from now on.
+pub struct Watch(pub Recipient<Syn, PlanetEvent>);
+
+impl Message for Watch {
+    type Result = ();
+}
+
+/// Planet events recorded in the log after a transaction.
+pub struct PlanetEventsSince(pub i64);
+
+impl Message for PlanetEventsSince {
+    type Result = Result<Vec<PlanetEvent>>;
+}
+
 pub struct SearchPlanet(pub String);
 
 impl Message for SearchPlanet {
     type Result = Result<Value>;
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn films_keys_ignore_case_blanks_and_composition() {
+        assert_eq!(films_key(" Tatooine "), "tatooine");
+        assert_eq!(films_key("TATOOINE"), films_key("tatooine"));
+        // decomposed e and acute accent
+        assert_eq!(films_key("Cate\u{301}"), films_key("Caté"));
+        // full width letters
+        assert_eq!(films_key("Ｈｏｔｈ"), "hoth");
+        assert_eq!(films_key("Straße"), films_key("STRASSE"));
+        assert_eq!(films_key("ΣΟΦΟΣ"), films_key("σοφος"));
+    }
+}
@@ -0,0 +1,284 @@
+use actix::Message;
+use errors::Result;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::value::Value;
+use std::marker::PhantomData;
+use uuid::Uuid;
+use super::Created;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Text,
+    Long,
+}
+
+/// A SWAPI resource stored under its own attribute namespace with the planets
+/// CRSD semantics, every resource has a `:<namespace>/uuid` identity attribute.
+/// Fields missing from a request are not asserted at all.
+pub trait Resource: Serialize + DeserializeOwned + Send + 'static {
+    /// Attribute namespace, `person` for `:person/name`.
+    const NAMESPACE: &'static str;
+    /// Route segment under `/sw`.
+    const PATH: &'static str;
+    /// Fulltext attribute used to search by name.
+    const NAME: &'static str;
+    /// Attributes besides the uuid.
+    const FIELDS: &'static [(&'static str, FieldType)];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Person {
+    pub name: Option<String>,
+    pub height: Option<String>,
+    pub mass: Option<String>,
+    pub hair_color: Option<String>,
+    pub skin_color: Option<String>,
+    pub eye_color: Option<String>,
+    pub birth_year: Option<String>,
+    pub gender: Option<String>,
+}
+
+impl Resource for Person {
+    const NAMESPACE: &'static str = "person";
+    const PATH: &'static str = "people";
+    const NAME: &'static str = "name";
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("name", FieldType::Text),
+        ("height", FieldType::Text),
+        ("mass", FieldType::Text),
+        ("hair_color", FieldType::Text),
+        ("skin_color", FieldType::Text),
+        ("eye_color", FieldType::Text),
+        ("birth_year", FieldType::Text),
+        ("gender", FieldType::Text),
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Film {
+    pub title: Option<String>,
+    pub episode_id: Option<i64>,
+    pub opening_crawl: Option<String>,
+    pub director: Option<String>,
+    pub producer: Option<String>,
+    pub release_date: Option<String>,
+}
+
+impl Resource for Film {
+    const NAMESPACE: &'static str = "film";
+    const PATH: &'static str = "films";
+    const NAME: &'static str = "title";
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("title", FieldType::Text),
+        ("episode_id", FieldType::Long),
+        ("opening_crawl", FieldType::Text),
+        ("director", FieldType::Text),
+        ("producer", FieldType::Text),
+        ("release_date", FieldType::Text),
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Starship {
+    pub name: Option<String>,
+    pub model: Option<String>,
+    pub manufacturer: Option<String>,
+    pub cost_in_credits: Option<String>,
+    pub length: Option<String>,
+    pub max_atmosphering_speed: Option<String>,
+    pub crew: Option<String>,
+    pub passengers: Option<String>,
+    pub cargo_capacity: Option<String>,
+    pub consumables: Option<String>,
+    pub hyperdrive_rating: Option<String>,
+    #[serde(rename = "MGLT")]
+    pub mglt: Option<String>,
+    pub starship_class: Option<String>,
+}
+
+impl Resource for Starship {
+    const NAMESPACE: &'static str = "starship";
+    const PATH: &'static str = "starships";
+    const NAME: &'static str = "name";
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("name", FieldType::Text),
+        ("model", FieldType::Text),
+        ("manufacturer", FieldType::Text),
+        ("cost_in_credits", FieldType::Text),
+        ("length", FieldType::Text),
+        ("max_atmosphering_speed", FieldType::Text),
+        ("crew", FieldType::Text),
+        ("passengers", FieldType::Text),
+        ("cargo_capacity", FieldType::Text),
+        ("consumables", FieldType::Text),
+        ("hyperdrive_rating", FieldType::Text),
+        ("MGLT", FieldType::Text),
+        ("starship_class", FieldType::Text),
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Vehicle {
+    pub name: Option<String>,
+    pub model: Option<String>,
+    pub manufacturer: Option<String>,
+    pub cost_in_credits: Option<String>,
+    pub length: Option<String>,
+    pub max_atmosphering_speed: Option<String>,
+    pub crew: Option<String>,
+    pub passengers: Option<String>,
+    pub cargo_capacity: Option<String>,
+    pub consumables: Option<String>,
+    pub vehicle_class: Option<String>,
+}
+
+impl Resource for Vehicle {
+    const NAMESPACE: &'static str = "vehicle";
+    const PATH: &'static str = "vehicles";
+    const NAME: &'static str = "name";
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("name", FieldType::Text),
+        ("model", FieldType::Text),
+        ("manufacturer", FieldType::Text),
+        ("cost_in_credits", FieldType::Text),
+        ("length", FieldType::Text),
+        ("max_atmosphering_speed", FieldType::Text),
+        ("crew", FieldType::Text),
+        ("passengers", FieldType::Text),
+        ("cargo_capacity", FieldType::Text),
+        ("consumables", FieldType::Text),
+        ("vehicle_class", FieldType::Text),
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Species {
+    pub name: Option<String>,
+    pub classification: Option<String>,
+    pub designation: Option<String>,
+    pub average_height: Option<String>,
+    pub skin_colors: Option<String>,
+    pub hair_colors: Option<String>,
+    pub eye_colors: Option<String>,
+    pub average_lifespan: Option<String>,
+    pub language: Option<String>,
+}
+
+impl Resource for Species {
+    const NAMESPACE: &'static str = "species";
+    const PATH: &'static str = "species";
+    const NAME: &'static str = "name";
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("name", FieldType::Text),
+        ("classification", FieldType::Text),
+        ("designation", FieldType::Text),
+        ("average_height", FieldType::Text),
+        ("skin_colors", FieldType::Text),
+        ("hair_colors", FieldType::Text),
+        ("eye_colors", FieldType::Text),
+        ("average_lifespan", FieldType::Text),
+        ("language", FieldType::Text),
+    ];
+}
+
+pub struct CreateResource<T: Resource>(pub T);
+
+impl<T: Resource> Message for CreateResource<T> {
+    type Result = Result<Created>;
+}
+
+pub struct ReadResources<T: Resource>(pub PhantomData<T>);
+
+impl<T: Resource> Message for ReadResources<T> {
+    type Result = Result<Value>;
+}
+
+pub struct SearchResource<T: Resource>(pub String, pub PhantomData<T>);
+
+impl<T: Resource> Message for SearchResource<T> {
+    type Result = Result<Value>;
+}
+
+pub struct GetResource<T: Resource>(pub Uuid, pub Vec<String>, pub PhantomData<T>);
+
+impl<T: Resource> Message for GetResource<T> {
+    type Result = Result<Value>;
+}
+
+pub struct DeleteResource<T: Resource>(pub Uuid, pub PhantomData<T>);
+
+impl<T: Resource> Message for DeleteResource<T> {
+    type Result = Result<::mentat::TxReport>;
+}
+
+/// Ref typed attribute `:<from>/<name>` pointing to entities of the `to` namespace.
+#[derive(Debug)]
+pub struct Relation {
+    pub from: &'static str,
+    pub name: &'static str,
+    pub to: &'static str,
+    pub many: bool,
+    /// Relation kept in sync on the target entity.
+    pub inverse: Option<&'static str>,
+}
+
+impl Relation {
+    pub fn attribute(&self) -> String {
+        format!(":{}/{}", self.from, self.name)
+    }
+
+    pub fn inverse(&self) -> Option<&'static Relation> {
+        self.inverse.and_then(|name| relation(self.to, name))
+    }
+}
+
+pub const RELATIONS: &'static [Relation] = &[
+    Relation{from: "planet", name: "films", to: "film", many: true, inverse: None},
+    Relation{from: "planet", name: "residents", to: "person", many: true, inverse: Some("homeworld")},
+    Relation{from: "person", name: "homeworld", to: "planet", many: false, inverse: Some("residents")},
+    Relation{from: "person", name: "films", to: "film", many: true, inverse: None},
+];
+
+pub fn relation(ns: &str, name: &str) -> Option<&'static Relation> {
+    RELATIONS.iter().find(|r| r.from == ns && r.name == name)
+}
+
+/// Relation behind a ref attribute ident, `:person/homeworld`.
+pub fn relation_of(attribute: &str) -> Option<&'static Relation> {
+    let mut parts = attribute.trim_left_matches(':').splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(ns), Some(name)) => relation(ns, name),
+        _ => None,
+    }
+}
+
+/// Entities related to `uuid`, inlined as deep as the `expand` paths go.
+pub struct GetRelated {
+    pub ns: &'static str,
+    pub uuid: Uuid,
+    pub relation: String,
+    pub expand: Vec<String>,
+}
+
+impl Message for GetRelated {
+    type Result = Result<Value>;
+}
+
+/// Links, or unlinks, `target` to `uuid` through a relation.
+pub struct Link {
+    pub ns: &'static str,
+    pub uuid: Uuid,
+    pub relation: String,
+    pub target: Uuid,
+    pub link: bool,
+}
+
+impl Message for Link {
+    type Result = Result<::mentat::TxReport>;
+}
@@ -0,0 +1,75 @@
+use actix::Message;
+use errors::Result;
+use mentat::TxReport;
+use serde_json::value::Value;
+use uuid::Uuid;
+use super::Created;
+
+/// Webhook registration, a secret is generated when none is given.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: Option<String>,
+}
+
+/// Registers a webhook notified of every planet change. Only its uuid and url
+/// are stored, the secret is kept by `WebhookDispatcher`.
+pub struct RegisterWebhook {
+    pub uuid: Uuid,
+    pub url: String,
+}
+
+impl Message for RegisterWebhook {
+    type Result = Result<Created>;
+}
+
+/// Secret signing the payloads of a webhook.
+pub struct WebhookSecret {
+    pub webhook: Uuid,
+    pub secret: String,
+}
+
+impl Message for WebhookSecret {
+    type Result = Result<()>;
+}
+
+pub struct ReadWebhooks;
+
+impl Message for ReadWebhooks {
+    type Result = Result<Value>;
+}
+
+pub struct DeleteWebhook(pub Uuid);
+
+impl Message for DeleteWebhook {
+    type Result = Result<TxReport>;
+}
+
+/// Drops the secret and pending deliveries of a deleted webhook.
+pub struct ForgetWebhook(pub Uuid);
+
+impl Message for ForgetWebhook {
+    type Result = Result<()>;
+}
+
+/// Delivery log of a webhook, latest first.
+pub struct WebhookDeliveries(pub Uuid);
+
+impl Message for WebhookDeliveries {
+    type Result = Result<Value>;
+}
+
+/// A registered webhook, with the secret stored by earlier versions if any.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub uuid: Uuid,
+    pub url: String,
+    pub legacy_secret: Option<String>,
+}
+
+/// Every registered webhook.
+pub struct WebhookTargets;
+
+impl Message for WebhookTargets {
+    type Result = Result<Vec<Target>>;
+}
@@ -27,9 +27,75 @@ pub enum ErrorKind {
     #[error_chain(foreign)]
     Parse(::std::num::ParseIntError),
 
+    #[error_chain(foreign)]
+    Json(::serde_json::Error),
+
     #[error_chain(foreign)]
     Failure(::failure::Compat<::failure::Error>),
 
+    #[error_chain(foreign)]
+    Redis(::redis::RedisError),
+
     #[error_chain(custom)]
     Poisoned(String),
+
+    #[error_chain(custom)]
+    BadRequest(String),
+
+    #[error_chain(custom)]
+    InvalidUuid(String),
+
+    #[error_chain(custom)]
+    Forbidden(String),
+
+    #[error_chain(custom)]
+    NotFound(String),
+
+    #[error_chain(custom)]
+    Conflict(String),
+
+    #[error_chain(custom)]
+    Upstream(String),
+
+    #[error_chain(custom)]
+    UpstreamTimeout(String),
+}
+
+impl ErrorKind {
+    /// Machine readable code reported on json error responses.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ErrorKind::BadRequest(_) => "bad_request",
+            ErrorKind::InvalidUuid(_) => "invalid_uuid",
+            ErrorKind::Forbidden(_) => "forbidden",
+            ErrorKind::NotFound(_) => "not_found",
+            ErrorKind::Conflict(_) => "conflict",
+            ErrorKind::Upstream(_) => "upstream_error",
+            ErrorKind::UpstreamTimeout(_) => "upstream_timeout",
+            _ => "internal_error",
+        }
+    }
+
+    /// Http status code for this kind of error.
+    pub fn status(&self) -> u16 {
+        match *self {
+            ErrorKind::BadRequest(_) | ErrorKind::InvalidUuid(_) => 400,
+            ErrorKind::Forbidden(_) => 403,
+            ErrorKind::NotFound(_) => 404,
+            ErrorKind::Conflict(_) => 409,
+            ErrorKind::Upstream(_) => 502,
+            ErrorKind::UpstreamTimeout(_) => 504,
+            _ => 500,
+        }
+    }
+
+    /// Human readable message, internal failures are not detailed to clients.
+    pub fn message(&self) -> String {
+        match *self {
+            ErrorKind::BadRequest(ref m) | ErrorKind::InvalidUuid(ref m) | ErrorKind::Forbidden(ref m) | ErrorKind::NotFound(ref m)
+            | ErrorKind::Conflict(ref m) | ErrorKind::Upstream(ref m) 
+            | ErrorKind::UpstreamTimeout(ref m) => m.clone(),
+            _ => String::from("internal server error"),
+        }
+    }
 }
\ No newline at end of file
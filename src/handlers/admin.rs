@@ -0,0 +1,173 @@
+use futures::{Future, future};
+use actix_web::{HttpRequest, HttpResponse, Error, http::header};
+use domain::{PlanetNames, WarmPlanets};
+use domain::cache::{CacheStats, LookupStats, CacheEntries, CacheEntry, InvalidateFilms};
+use errors::{Result, Error as ApiError, ErrorKind};
+use serde_json::value::Value;
+use std::net::SocketAddr;
+use super::{request_id, respond, error_response};
+
+/// Admin calls are served to local callers only, or to callers bearing the
+/// `--admin-token` when one is set.
+fn authorize(req: &HttpRequest<::actors::State>) -> Result<()> {
+    let authorization = req.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok());
+    allowed(req.state().admin_token.as_ref().map(|t| t.as_str()), req.peer_addr(), authorization)
+}
+
+fn allowed(token: Option<&str>, peer: Option<SocketAddr>, authorization: Option<&str>) -> Result<()> {
+    let ok = match token {
+        Some(token) => authorization.map_or(false, |a| a.starts_with("Bearer ")
+            && same(a["Bearer ".len()..].trim().as_bytes(), token.as_bytes())),
+        None => peer.map_or(false, |p| p.ip().is_loopback()),
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(ApiError::from_kind(ErrorKind::Forbidden(String::from(match token {
+            Some(_) => "admin api requires the admin bearer token",
+            None => "admin api is served to local callers only",
+        }))))
+    }
+}
+
+/// Compares in constant time, so the token can't be guessed from timings.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Circuit breaker state and counters of the upstream films api.
+#[cfg_attr(feature="flame_it", flame)]
+pub fn upstream(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let breaker = {req.state().upstream.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(future::ok(HttpResponse::Ok().json(breaker.stats())))
+}
+
+fn name_param(req: &HttpRequest<::actors::State>) -> String {
+    String::from(req.match_info().get("name").unwrap_or(""))
+}
+
+/// Cache size and evictions, with the lookups it answered.
+#[cfg_attr(feature="flame_it", flame)]
+pub fn stats(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let films = {req.state().films.clone()};
+    let cache = {req.state().cache.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(cache.send(CacheStats).join(films.send(LookupStats))
+                .then(move |res| respond(&rid, res.map(|(cache, lookups)| cache.and_then(|c| lookups.map(|l| merged(c, l))))
+                    , |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+fn merged(mut a: Value, b: Value) -> Value {
+    if let (Some(a), Value::Object(b)) = (a.as_object_mut(), b) {
+        a.extend(b);
+    }
+    a
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn entries(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let cache = {req.state().cache.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(cache.send(CacheEntries)
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn entry(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let cache = {req.state().cache.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(cache.send(CacheEntry(name_param(&req)))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+/// Invalidates the cached films of the planet named in the path.
+#[cfg_attr(feature="flame_it", flame)]
+pub fn invalidate(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let cache = {req.state().cache.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(cache.send(InvalidateFilms(Some(name_param(&req))))
+                .then(move |res| respond(&rid, res, |_| HttpResponse::NoContent().finish()))
+    )
+}
+
+/// Invalidates every cached films count.
+#[cfg_attr(feature="flame_it", flame)]
+pub fn clear(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let cache = {req.state().cache.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(cache.send(InvalidateFilms(None))
+                .then(move |res| respond(&rid, res, |n| HttpResponse::Ok().json(json!({"invalidated": n}))))
+    )
+}
+
+/// Pre-fetches in background the films of every planet in the store not
+/// cached or stale, queued to the refresher ahead of its periodic refreshes,
+/// one at a time so upstream is not hit at once.
+#[cfg_attr(feature="flame_it", flame)]
+pub fn warm(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let refresher = {req.state().refresher.clone()};
+    let rid = request_id(&req);
+    if let Err(e) = authorize(&req) {
+        return Box::new(future::ok(error_response(&rid, &e)));
+    }
+    Box::new(read.send(PlanetNames)
+                .then(move |res| respond(&rid, res, |names| {
+                    let n = names.len();
+                    refresher.do_send(WarmPlanets(names));
+                    HttpResponse::Accepted().json(json!({"warming": n}))
+                }))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(addr: &str) -> Option<SocketAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn serves_local_callers_without_a_token() {
+        assert!(allowed(None, peer("127.0.0.1:5000"), None).is_ok());
+        assert!(allowed(None, peer("[::1]:5000"), None).is_ok());
+        assert!(allowed(None, peer("10.0.0.7:5000"), Some("Bearer guess")).is_err());
+        assert!(allowed(None, None, None).is_err());
+    }
+
+    #[test]
+    fn requires_the_token_when_set() {
+        let token = Some("s3cr3t");
+        assert!(allowed(token, peer("10.0.0.7:5000"), Some("Bearer s3cr3t")).is_ok());
+        assert!(allowed(token, peer("127.0.0.1:5000"), None).is_err());
+        assert!(allowed(token, peer("127.0.0.1:5000"), Some("Bearer s3cr3")).is_err());
+        assert!(allowed(token, peer("127.0.0.1:5000"), Some("s3cr3t")).is_err());
+        match allowed(token, None, None) {
+            Err(e) => assert_eq!(e.kind().status(), 403),
+            Ok(_) => panic!("allowed without a token"),
+        }
+    }
+}
@@ -1,94 +1,334 @@
-use futures::{Future,future};
-use actix_web::{HttpRequest, HttpResponse, HttpMessage, Error, AsyncResponder, Query, Body, http::{StatusCode, header}};
-use domain::{Planet, ReadPlanets, DeletePlanet, SearchPlanet, GetPlanet};
-use actors::getfilms;
+use futures::{Future, Stream, future, future::Either, stream, sync::mpsc::unbounded};
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, Error, AsyncResponder, Body, ws, http::{StatusCode, header}};
+use actix::{Addr, Syn, MailboxError};
+use domain::{Planet, PlanetPatch, ReadFilms, ReadPlanets, DeletePlanet, SearchPlanet, GetPlanet, UpdatePlanet, PatchPlanet, PlanetSort
+    , GetPlanetAsOf, PlanetHistory, RestorePlanet, DeletedPlanets
+    , PlanetChanges, PlanetEvent, PlanetEventsSince, Subscribe, Keyset};
+use domain::resources::{GetRelated, Link};
+use actors::{ReadExecutor, relations::MAX_EXPAND_DEPTH, socket::PlanetSocket};
+use errors::ErrorKind;
+use mentat::TxReport;
+use serde::de::DeserializeOwned;
+use serde_urlencoded;
+use serde_json;
+use txlog::AsOf;
 use uuid::Uuid;
+use bytes::Bytes;
+
+pub mod resources;
+pub mod webhooks;
+pub mod admin;
+
+/// Request id echoed on every error, taken from `X-Request-Id` when the client sends one.
+fn request_id<S>(req: &HttpRequest<S>) -> String {
+    req.headers().get("X-Request-Id")
+        .and_then(|h| h.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Builds the json error body `{"error": {"code", "message", "request_id"}}`.
+fn error_response(rid: &str, e: &::errors::Error) -> HttpResponse {
+    let kind = e.kind();
+    match kind.status() {
+        500 => warn!("request {} error: {:?}", rid, e),
+        _ => debug!("request {} error: {:?}", rid, e),
+    }
+    let status = StatusCode::from_u16(kind.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    HttpResponse::build(status)
+        .header("X-Request-Id", rid)
+        .json(json!({
+            "error": {
+                "code": kind.code(),
+                "message": kind.message(),
+                "request_id": rid,
+            }
+        }))
+}
+
+/// Maps an actor reply into a response, both mailbox and handler failures become json errors.
+fn respond<T, F>(rid: &str, res: ::std::result::Result<::errors::Result<T>, MailboxError>, f: F)
+    -> ::std::result::Result<HttpResponse, Error> where
+    F: FnOnce(T) -> HttpResponse
+{
+    match res {
+        Ok(Ok(out)) => Ok(f(out)), // <- send response
+        Ok(Err(e)) => Ok(error_response(rid, &e)),
+        Err(e) => Ok(error_response(rid, &::errors::Error::from_kind(ErrorKind::Msg(format!("{}", e))))),
+    }
+}
+
+/// Query string parameters, a malformed query is a bad request like any other.
+fn query_param<T: DeserializeOwned, S>(req: &HttpRequest<S>) -> ::errors::Result<T> {
+    serde_urlencoded::from_str(req.query_string())
+        .map_err(|e| ::errors::Error::from_kind(ErrorKind::BadRequest(format!("invalid query: {}", e))))
+}
+
+fn uuid_param<S>(req: &HttpRequest<S>) -> ::errors::Result<Uuid> {
+    named_uuid_param(req, "uuid")
+}
+
+fn named_uuid_param<S>(req: &HttpRequest<S>, name: &str) -> ::errors::Result<Uuid> {
+    match req.match_info().get(name) {
+        Some(uuid) => Uuid::parse_str(uuid)
+            .map_err(|e| ::errors::Error::from_kind(ErrorKind::InvalidUuid(format!("invalid uuid {}: {}", uuid, e)))),
+        None => Err(::errors::Error::from_kind(ErrorKind::InvalidUuid(format!("missing {}", name)))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExpandParam {
+    pub expand: Option<String>,
+}
+
+/// Comma separated relation paths, `films,residents.homeworld`.
+fn expand_param(info: &ExpandParam) -> ::errors::Result<Vec<String>> {
+    let paths : Vec<String> = match info.expand {
+        Some(ref expand) => expand.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()).map(String::from).collect(),
+        None => Vec::new(),
+    };
+    match paths.iter().find(|p| p.split('.').count() > MAX_EXPAND_DEPTH) {
+        Some(p) => Err(::errors::Error::from_kind(ErrorKind::BadRequest(
+            format!("expand path too deep: {}, at most {} levels", p, MAX_EXPAND_DEPTH)))),
+        None => Ok(paths),
+    }
+}
+
+fn bad_json(rid: &str, e: ::actix_web::error::JsonPayloadError) -> ::std::result::Result<HttpResponse, Error> {
+    Ok(error_response(rid, &::errors::Error::from_kind(ErrorKind::BadRequest(format!("invalid json: {}", e)))))
+}
 
 #[cfg_attr(feature="flame_it", flame)]
 pub fn create(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
     let db = {req.state().db.clone()};
-    let cache = {req.state().cache.clone()};
-    let processor = {req.state().processor.clone()};
+    let read = {req.state().read.clone()};
+    let films = {req.state().films.clone()};
+    let rid = request_id(&req);
     req.json()
-        .from_err()
-        .and_then(move |p : Planet|  {
-            let name = p.name.clone();
-
-            let _ = getfilms(&name, processor, cache, |_| ());
-
-            db.send(p)
-                .from_err()
-                .and_then(|res| {
-                    match res {
-                        Ok(tx) => {                            
-                            let map = json!({
-                                  "tx_id": tx.tx_id,
-                                  "tx_instant": tx.tx_instant,
-                            });
-                            Ok(HttpResponse::Ok().json(map))
-                        } // <- send response
-                        Err(e) => {
-                            warn!("error: {:?}", e);
-                            Ok(HttpResponse::InternalServerError().into())
+        .then(move |res| match res {
+            Ok(p) => {
+                let p : Planet = p;
+                films.do_send(ReadFilms(p.name.clone())); // warms the films cache
+
+                Either::A(db.send(p)
+                    .then(move |res| -> Box<Future<Item=HttpResponse, Error=Error>> {
+                        match res {
+                            Ok(Ok(created)) => {
+                                let uuid = created.uuid;
+                                debug!("created {} on tx: \t {}", uuid, created.report.tx_id);
+                                Box::new(read.send(GetPlanet(uuid, Vec::new()))
+                                    .then(move |res| respond(&rid, res, |out| {
+                                        HttpResponse::Created()
+                                            .header(header::LOCATION, format!("/sw/{}", uuid))
+                                            .json(out)
+                                    })))
+                            }
+                            res => Box::new(future::result(respond(&rid, res, |_| HttpResponse::Created().finish()))),
                         }
-                    }
-                })            
+                    }))
+            }
+            Err(e) => Either::B(future::result(bad_json(&rid, e))),
         })
         .responder()
 }
 
+const DEFAULT_PAGE_SIZE: usize = 10;
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct PageParam {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    pub as_of: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    at: Keyset,
+}
+
+/// Opaque cursor, the hex encoded sort and keyset of a page boundary.
+fn encode_cursor(sort: PlanetSort, at: &Keyset) -> String {
+    let cursor = Cursor{sort: String::from(sort.as_str()), at: at.clone()};
+    json!(cursor).to_string().bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<(PlanetSort, Keyset)> {
+    if cursor.len() % 2 != 0 || !cursor.is_ascii() {
+        return None;
+    }
+    let bytes : Option<Vec<u8>> = (0..cursor.len() / 2)
+        .map(|i| u8::from_str_radix(&cursor[2 * i..2 * i + 2], 16).ok())
+        .collect();
+    let decoded : Cursor = serde_json::from_slice(&bytes?).ok()?;
+    let sort = PlanetSort::parse(&decoded.sort)?;
+    if decoded.at.keys.len() != sort.arity() {
+        return None;
+    }
+    Some((sort, decoded.at))
+}
+
+fn page_param(info: &PageParam) -> ::errors::Result<ReadPlanets> {
+    let bad = |m: String| ::errors::Error::from_kind(ErrorKind::BadRequest(m));
+    let limit = info.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(bad(format!("limit must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+    let sort = match info.sort {
+        Some(ref sort) => Some(PlanetSort::parse(sort)
+            .ok_or_else(|| bad(format!("invalid sort: {}, expected name, -name or climate", sort)))?),
+        None => None,
+    };
+    let (sort, keyset) = match info.cursor {
+        Some(ref cursor) => {
+            let (at_sort, at) = decode_cursor(cursor).ok_or_else(|| bad(format!("invalid cursor: {}", cursor)))?;
+            match sort {
+                Some(sort) if sort != at_sort => return Err(bad(format!(
+                    "sort {} conflicts with the cursor, sorted by {}", sort.as_str(), at_sort.as_str()))),
+                _ => (),
+            }
+            if info.offset.is_some() {
+                return Err(bad(String::from("offset can not be combined with a cursor")));
+            }
+            (at_sort, Some(at))
+        }
+        None => (sort.unwrap_or_default(), None),
+    };
+    let as_of = match info.as_of {
+        Some(ref as_of) => Some(as_of_param(as_of)?),
+        None => None,
+    };
+    Ok(ReadPlanets{offset: info.offset.unwrap_or(0), limit: limit, sort: sort, keyset: keyset, as_of: as_of})
+}
+
+fn as_of_param(as_of: &str) -> ::errors::Result<AsOf> {
+    AsOf::parse(as_of).ok_or_else(|| ::errors::Error::from_kind(ErrorKind::BadRequest(
+        format!("invalid as_of: {}, expected a transaction id or a RFC3339 instant", as_of))))
+}
+
 #[cfg_attr(feature="flame_it", flame)]
 pub fn read(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
     let read = {req.state().read.clone()};
-    Box::new(read.send(ReadPlanets{})
-                .from_err()
-                .and_then(|res| {
-                    match res {
-                        Ok(out) => {                            
-                            Ok(HttpResponse::Ok().json(out))
-                        } // <- send response
-                        Err(e) => {
-                            warn!("error: {:?}", e);
-                            Ok(HttpResponse::InternalServerError().into())
-                        }
+    let rid = request_id(&req);
+    let page = match query_param::<PageParam, _>(&req).and_then(|info| page_param(&info)) {
+        Ok(p) => p,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+    let path = String::from(req.path());
+    let (limit, sort) = (page.limit, page.sort);
+    Box::new(read.send(page)
+                .then(move |res| respond(&rid, res, |page| {
+                    // past reads keep pointing at the same resolved transaction
+                    let at = page.as_of.map_or(String::new(), |tx| format!("&as_of={}", tx));
+                    let link = |k: &Keyset| format!("{}?limit={}&cursor={}{}", path, limit, encode_cursor(sort, k), at);
+                    let next = page.next.as_ref().map(&link);
+                    let previous = page.previous.as_ref().map(&link);
+                    let mut envelope = json!({
+                        "count": page.count,
+                        "next": next,
+                        "previous": previous,
+                        "results": page.results,
+                    });
+                    if let Some(tx) = page.as_of {
+                        envelope["as_of"] = json!(tx);
                     }
-                })  
-    )   
+                    HttpResponse::Ok().json(envelope)
+                }))
+    )
 }
 
 #[cfg_attr(feature="flame_it", flame)]
 pub fn delete(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
     let db = {req.state().db.clone()};
-    let uuid = {
-        if let Some(uuid) = req.match_info().get("uuid") {
-            match Uuid::parse_str(uuid) {
-                Ok(u) => u,
-                Err(_) => return Box::new(future::ok(HttpResponse::InternalServerError().into())),
-            }
-        } else {
-            return Box::new(future::ok(HttpResponse::InternalServerError().into()));
-        }
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
     };
 
     debug!("got uuid: \t {}", uuid);
 
     Box::new(db.send(DeletePlanet(uuid))
-        .from_err()
-        .and_then(|res| {
-            match res {
-                Ok(tx) => {                      
-                    let map = json!({
-                        "tx_id": tx.tx_id,
-                        "tx_instant": tx.tx_instant,
-                    });
-                    Ok(HttpResponse::Ok().json(map))
-                } // <- send response
-                Err(e) => {
-                    warn!("error: {:?}", e);
-                    Ok(HttpResponse::InternalServerError().into())
+        .then(move |res| respond(&rid, res, |tx| {
+            let map = json!({
+                "tx_id": tx.tx_id,
+                "tx_instant": tx.tx_instant,
+            });
+            HttpResponse::Ok().json(map)
+        })))
+}
+
+/// Replies with the current representation of a planet after a successful update.
+fn updated(read: Addr<Syn, ReadExecutor>, rid: String, uuid: Uuid,
+           res: ::std::result::Result<::errors::Result<TxReport>, MailboxError>)
+    -> Box<Future<Item=HttpResponse, Error=Error>> {
+    match res {
+        Ok(Ok(tx)) => {
+            debug!("updated {} on tx: \t {}", uuid, tx.tx_id);
+            Box::new(read.send(GetPlanet(uuid, Vec::new()))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out))))
+        }
+        res => Box::new(future::result(respond(&rid, res, |_| HttpResponse::Ok().finish()))),
+    }
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn update(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let read = {req.state().read.clone()};
+    let films = {req.state().films.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    debug!("got uuid: \t {}", uuid);
+
+    req.json()
+        .then(move |res| match res {
+            Ok(p) => {
+                let p : Planet = p;
+                films.do_send(ReadFilms(p.name.clone()));
+
+                Either::A(db.send(UpdatePlanet(uuid, p))
+                    .then(move |res| updated(read, rid, uuid, res)))
+            }
+            Err(e) => Either::B(future::result(bad_json(&rid, e))),
+        })
+        .responder()
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn patch(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let read = {req.state().read.clone()};
+    let films = {req.state().films.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    debug!("got uuid: \t {}", uuid);
+
+    req.json()
+        .then(move |res| match res {
+            Ok(p) => {
+                let p : PlanetPatch = p;
+                if let Some(ref name) = p.name {
+                    films.do_send(ReadFilms(name.clone()));
                 }
+
+                Either::A(db.send(PatchPlanet(uuid, p))
+                    .then(move |res| updated(read, rid, uuid, res)))
             }
-        }))
+            Err(e) => Either::B(future::result(bad_json(&rid, e))),
+        })
+        .responder()
 }
 
 #[derive(Deserialize)]
@@ -97,55 +337,221 @@ pub struct SearchParam {
 }
 
 #[cfg_attr(feature="flame_it", flame)]
-pub fn search(req: HttpRequest<::actors::State>, info: Query<SearchParam>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+pub fn search(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
     let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let info : SearchParam = match query_param(&req) {
+        Ok(info) => info,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
     debug!("got search: \t {}", info.search);
     Box::new(read.send(SearchPlanet(info.search.clone()))
-                .from_err()
-                .and_then(|res| {
-                    match res {
-                        Ok(out) => {                            
-                            Ok(HttpResponse::Ok().json(out))
-                        } // <- send response
-                        Err(e) => {
-                            warn!("error: {:?}", e);
-                            Ok(HttpResponse::InternalServerError().into())
-                        }
-                    }
-                })  
-    )   
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[derive(Deserialize)]
+pub struct PlanetParam {
+    pub expand: Option<String>,
+    pub as_of: Option<String>,
 }
 
 #[cfg_attr(feature="flame_it", flame)]
 pub fn id(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
     let read = {req.state().read.clone()};
-    let uuid = {
-        if let Some(uuid) = req.match_info().get("uuid") {
-            match Uuid::parse_str(uuid) {
-                Ok(u) => u,
-                Err(_) => return Box::new(future::ok(HttpResponse::InternalServerError().into())),
-            }
-        } else {
-            return Box::new(future::ok(HttpResponse::InternalServerError().into()));
-        }
+    let rid = request_id(&req);
+    let info : PlanetParam = match query_param(&req) {
+        Ok(info) => info,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+    let expand = ExpandParam{expand: info.expand.clone()};
+    let (uuid, expand) = match uuid_param(&req).and_then(|u| expand_param(&expand).map(|e| (u, e))) {
+        Ok(p) => p,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
     };
 
-    debug!("got uuid: \t {}", uuid); 
+    debug!("got uuid: \t {}", uuid);
 
-    Box::new(read.send(GetPlanet(uuid))
-                .from_err()
-                .and_then(|res| {
-                    match res {
-                        Ok(out) => {                            
-                            Ok(HttpResponse::Ok().json(out))
-                        } // <- send response
-                        Err(e) => {
-                            warn!("error: {:?}", e);
-                            Ok(HttpResponse::InternalServerError().into())
-                        }
-                    }
-                })  
-    )         
+    if let Some(ref as_of) = info.as_of {
+        let as_of = match as_of_param(as_of) {
+            Ok(a) => a,
+            Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+        };
+        return Box::new(read.send(GetPlanetAsOf(uuid, as_of))
+                    .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out))));
+    }
+
+    Box::new(read.send(GetPlanet(uuid, expand))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn history(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    Box::new(read.send(PlanetHistory(uuid))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn restore(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    debug!("got uuid: \t {}", uuid);
+
+    Box::new(db.send(RestorePlanet(uuid))
+        .then(move |res| updated(read, rid, uuid, res)))
+}
+
+#[derive(Deserialize)]
+pub struct DeletedParam {
+    pub limit: Option<usize>,
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn deleted(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let info : DeletedParam = match query_param(&req) {
+        Ok(info) => info,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+    let limit = ::std::cmp::min(info.limit.unwrap_or(DEFAULT_PAGE_SIZE), MAX_PAGE_SIZE);
+
+    Box::new(read.send(DeletedPlanets(limit))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ChangesParam {
+    pub since: Option<i64>,
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn changes(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let info : ChangesParam = match query_param(&req) {
+        Ok(info) => info,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    Box::new(read.send(PlanetChanges(info.since.unwrap_or(0)))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+/// Server-Sent Events frame of a planet event, its transaction id is the event id.
+fn sse_frame(event: &PlanetEvent) -> Bytes {
+    Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", event.tx, event.change.as_str(), json!(event)))
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn events(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let broker = {req.state().events.clone()};
+    let rid = request_id(&req);
+    let last_id = match req.headers().get("Last-Event-ID") {
+        Some(h) => match h.to_str().ok().and_then(|id| id.trim().parse::<i64>().ok()) {
+            Some(id) => Some(id),
+            None => return Box::new(future::ok(error_response(&rid, &::errors::Error::from_kind(
+                ErrorKind::BadRequest(String::from("Last-Event-ID must be a transaction id")))))),
+        },
+        None => None,
+    };
+
+    // subscribed before reading the log so no commit is missed in between
+    let (tx, rx) = unbounded();
+    broker.do_send(Subscribe(tx));
+    let past = match last_id {
+        Some(id) => Either::A(read.send(PlanetEventsSince(id))),
+        None => Either::B(future::ok::<_, MailboxError>(Ok(Vec::new()))),
+    };
+
+    Box::new(past.then(move |res| respond(&rid, res, move |past: Vec<PlanetEvent>| {
+        let seen = past.last().map(|e| e.tx).or(last_id).unwrap_or(0);
+        let body = stream::iter_ok::<_, ()>(past)
+            .chain(rx.filter(move |e| e.tx > seen))
+            .map(|e| sse_frame(&e))
+            .map_err(|_| ::actix_web::error::ErrorInternalServerError("event stream closed"));
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .streaming(body)
+    })))
+}
+
+/// Websocket of planet search subscriptions.
+pub fn socket(req: HttpRequest<::actors::State>) -> ::std::result::Result<HttpResponse, Error> {
+    ws::start(req, PlanetSocket::default())
+}
+
+/// Entities related to an entity of namespace `ns` through the `relation` path segment.
+fn related_in(ns: &'static str, req: HttpRequest<::actors::State>) 
+    -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let params = uuid_param(&req)
+        .and_then(|u| query_param::<ExpandParam, _>(&req).and_then(|info| expand_param(&info)).map(|e| (u, e)));
+    let (uuid, expand) = match params {
+        Ok(p) => p,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+    let relation = String::from(req.match_info().get("relation").unwrap_or(""));
+
+    Box::new(read.send(GetRelated{ns: ns, uuid: uuid, relation: relation, expand: expand})
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+/// Links or unlinks the `target` entity through the `relation` path segment.
+fn link_in(ns: &'static str, req: HttpRequest<::actors::State>, link: bool) 
+    -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let rid = request_id(&req);
+    let (uuid, target) = match uuid_param(&req).and_then(|u| named_uuid_param(&req, "target").map(|t| (u, t))) {
+        Ok(p) => p,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+    let relation = String::from(req.match_info().get("relation").unwrap_or(""));
+
+    Box::new(db.send(Link{ns: ns, uuid: uuid, relation: relation, target: target, link: link})
+        .then(move |res| respond(&rid, res, |tx| {
+            let map = json!({
+                "tx_id": tx.tx_id,
+                "tx_instant": tx.tx_instant,
+            });
+            HttpResponse::Ok().json(map)
+        })))
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn related(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    related_in("planet", req)
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn link(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    link_in("planet", req, true)
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn unlink(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    link_in("planet", req, false)
 }
 
 pub fn redirect(_req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
@@ -153,4 +559,56 @@ pub fn redirect(_req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpRespo
             .header(header::LOCATION, "https://github.com/mmacedoeu/swapi/")
             .body(Body::Empty);
     Box::new(future::ok(resp))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyset(keys: &[&str]) -> Keyset {
+        Keyset{keys: keys.iter().map(|k| String::from(*k)).collect(),
+               uuid: String::from("2c2a8a4e-7fb4-4e5a-9a5e-0d7f3c1c8a11"), before: false}
+    }
+
+    fn param(cursor: Option<String>, sort: Option<&str>, offset: Option<usize>) -> PageParam {
+        PageParam{limit: None, offset: offset, cursor: cursor, sort: sort.map(String::from), as_of: None}
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let at = Keyset{before: true, ..keyset(&["arid", "Tatooine"])};
+        let cursor = encode_cursor(PlanetSort::Climate, &at);
+        assert!(cursor.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(decode_cursor(&cursor), Some((PlanetSort::Climate, at)));
+    }
+
+    #[test]
+    fn cursor_rejects_garbage() {
+        assert_eq!(decode_cursor("abc"), None);
+        assert_eq!(decode_cursor("zz"), None);
+        assert_eq!(decode_cursor(&"name:10".bytes().map(|b| format!("{:02x}", b)).collect::<String>()), None);
+        // a name keyset does not position a climate sort
+        let cursor = encode_cursor(PlanetSort::Name, &keyset(&["Tatooine"]));
+        let bytes : Vec<u8> = (0..cursor.len() / 2).map(|i| u8::from_str_radix(&cursor[2 * i..2 * i + 2], 16).unwrap()).collect();
+        let forged = String::from_utf8(bytes).unwrap().replace("\"name\"", "\"climate\"");
+        assert_eq!(decode_cursor(&forged.bytes().map(|b| format!("{:02x}", b)).collect::<String>()), None);
+    }
+
+    #[test]
+    fn cursor_keeps_its_sort() {
+        let cursor = encode_cursor(PlanetSort::NameDesc, &keyset(&["Tatooine"]));
+        let page = page_param(&param(Some(cursor.clone()), None, None)).unwrap();
+        assert_eq!(page.sort, PlanetSort::NameDesc);
+        assert_eq!(page.keyset, Some(keyset(&["Tatooine"])));
+        assert!(page_param(&param(Some(cursor.clone()), Some("-name"), None)).is_ok());
+        assert!(page_param(&param(Some(cursor.clone()), Some("name"), None)).is_err());
+        assert!(page_param(&param(Some(cursor), None, Some(10))).is_err());
+    }
+
+    #[test]
+    fn offset_without_cursor() {
+        let page = page_param(&param(None, Some("climate"), Some(10))).unwrap();
+        assert_eq!((page.sort, page.offset, page.keyset), (PlanetSort::Climate, 10, None));
+        assert!(page_param(&param(None, Some("terrain"), None)).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use futures::{Future, future, future::Either};
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, Error, AsyncResponder, http::header};
+use domain::resources::{Resource, CreateResource, ReadResources, SearchResource, GetResource, DeleteResource};
+use std::marker::PhantomData;
+use super::{request_id, error_response, respond, uuid_param, query_param, bad_json, expand_param, related_in, link_in
+    , SearchParam, ExpandParam};
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn create<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    req.json()
+        .then(move |res| match res {
+            Ok(r) => {
+                let r : T = r;
+                Either::A(db.send(CreateResource(r))
+                    .then(move |res| -> Box<Future<Item=HttpResponse, Error=Error>> {
+                        match res {
+                            Ok(Ok(created)) => {
+                                let uuid = created.uuid;
+                                debug!("created {} {} on tx: \t {}", T::NAMESPACE, uuid, created.report.tx_id);
+                                Box::new(read.send(GetResource::<T>(uuid, Vec::new(), PhantomData))
+                                    .then(move |res| respond(&rid, res, |out| {
+                                        HttpResponse::Created()
+                                            .header(header::LOCATION, format!("/sw/{}/{}", T::PATH, uuid))
+                                            .json(out)
+                                    })))
+                            }
+                            res => Box::new(future::result(respond(&rid, res, |_| HttpResponse::Created().finish()))),
+                        }
+                    }))
+            }
+            Err(e) => Either::B(future::result(bad_json(&rid, e))),
+        })
+        .responder()
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn read<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    Box::new(read.send(ReadResources::<T>(PhantomData))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn search<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let info : SearchParam = match query_param(&req) {
+        Ok(info) => info,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+    debug!("got {} search: \t {}", T::NAMESPACE, info.search);
+    Box::new(read.send(SearchResource::<T>(info.search.clone(), PhantomData))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn id<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    let params = uuid_param(&req)
+        .and_then(|u| query_param::<ExpandParam, _>(&req).and_then(|info| expand_param(&info)).map(|e| (u, e)));
+    let (uuid, expand) = match params {
+        Ok(p) => p,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    Box::new(read.send(GetResource::<T>(uuid, expand, PhantomData))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn related<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    related_in(T::NAMESPACE, req)
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn link<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    link_in(T::NAMESPACE, req, true)
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn unlink<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    link_in(T::NAMESPACE, req, false)
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn delete<T: Resource>(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    Box::new(db.send(DeleteResource::<T>(uuid, PhantomData))
+        .then(move |res| respond(&rid, res, |tx| {
+            let map = json!({
+                "tx_id": tx.tx_id,
+                "tx_instant": tx.tx_instant,
+            });
+            HttpResponse::Ok().json(map)
+        })))
+}
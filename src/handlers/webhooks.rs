@@ -0,0 +1,78 @@
+use futures::{Future, future, future::Either};
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, Error, AsyncResponder, http::header};
+use domain::webhooks::{Webhook, RegisterWebhook, WebhookSecret, ReadWebhooks, DeleteWebhook, ForgetWebhook, WebhookDeliveries};
+use uuid::Uuid;
+use super::{request_id, error_response, respond, uuid_param, bad_json};
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn create(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let webhooks = {req.state().webhooks.clone()};
+    let rid = request_id(&req);
+    req.json()
+        .then(move |res| match res {
+            Ok(w) => {
+                let w : Webhook = w;
+                // the secret is only ever returned here, and kept out of the store
+                let secret = w.secret.unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+                let (uuid, url) = (Uuid::new_v4(), w.url);
+                Either::A(webhooks.send(WebhookSecret{webhook: uuid, secret: secret.clone()})
+                    .then(move |res| match res {
+                        Ok(Ok(())) => Either::A(db.send(RegisterWebhook{uuid: uuid, url: url.clone()})
+                            .then(move |res| respond(&rid, res, |created| {
+                                debug!("registered webhook {} on tx: \t {}", created.uuid, created.report.tx_id);
+                                HttpResponse::Created()
+                                    .header(header::LOCATION, format!("/sw/webhooks/{}", created.uuid))
+                                    .json(json!({"uuid": created.uuid, "url": url, "secret": secret}))
+                            }))),
+                        res => Either::B(future::result(respond(&rid, res, |_| HttpResponse::Created().finish()))),
+                    }))
+            }
+            Err(e) => Either::B(future::result(bad_json(&rid, e))),
+        })
+        .responder()
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn read(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let read = {req.state().read.clone()};
+    let rid = request_id(&req);
+    Box::new(read.send(ReadWebhooks)
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn delete(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let db = {req.state().db.clone()};
+    let webhooks = {req.state().webhooks.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    Box::new(db.send(DeleteWebhook(uuid))
+        .then(move |res| respond(&rid, res, |tx| {
+            webhooks.do_send(ForgetWebhook(uuid));
+            let map = json!({
+                "tx_id": tx.tx_id,
+                "tx_instant": tx.tx_instant,
+            });
+            HttpResponse::Ok().json(map)
+        })))
+}
+
+#[cfg_attr(feature="flame_it", flame)]
+pub fn deliveries(req: HttpRequest<::actors::State>) -> Box<Future<Item=HttpResponse, Error=Error>> {
+    let webhooks = {req.state().webhooks.clone()};
+    let rid = request_id(&req);
+    let uuid = match uuid_param(&req) {
+        Ok(u) => u,
+        Err(e) => return Box::new(future::ok(error_response(&rid, &e))),
+    };
+
+    Box::new(webhooks.send(WebhookDeliveries(uuid))
+                .then(move |res| respond(&rid, res, |out| HttpResponse::Ok().json(out)))
+    )
+}
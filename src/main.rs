@@ -14,10 +14,22 @@ extern crate lru_time_cache;
 extern crate uuid;
 extern crate failure;
 extern crate crossbeam_channel;
+extern crate serde;
+extern crate chrono;
+extern crate hmac;
+extern crate sha2;
+extern crate rand;
+extern crate tokio_core;
+extern crate redis;
+extern crate unicode_normalization;
+extern crate caseless;
+extern crate serde_urlencoded;
 
 #[macro_use]
 extern crate mentat;
 #[macro_use]
+extern crate percent_encoding;
+#[macro_use]
 extern crate clap;
 #[macro_use]
 extern crate error_chain;
@@ -35,7 +47,14 @@ mod domain;
 mod actors;
 mod handlers;
 mod dirs;
+mod schema;
+mod txlog;
+mod upstream;
+mod cache;
+mod outbox;
 mod cli;
+#[cfg(test)]
+mod stub;
 
 quick_main!(run);
 
@@ -0,0 +1,274 @@
+use chrono::{DateTime, Duration, Utc};
+use errors::{Result, Error, ErrorKind};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Attempts before a delivery is given up as failed.
+pub const MAX_ATTEMPTS: i64 = 10;
+/// Delay before the first retry, doubled on every failed attempt.
+const BACKOFF_BASE_SECS: i64 = 2;
+const BACKOFF_MAX_SECS: i64 = 3600;
+/// Finished deliveries kept per webhook, pending ones are always kept.
+const MAX_FINISHED: usize = 100;
+
+/// Delay before the attempt following `attempts` failed ones.
+pub fn backoff(attempts: i64) -> Duration {
+    let exp = ::std::cmp::min(::std::cmp::max(attempts - 1, 0), 20) as u32;
+    Duration::seconds(::std::cmp::min(BACKOFF_BASE_SECS * 2i64.pow(exp), BACKOFF_MAX_SECS))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Delivered,
+    Failed,
+    Cancelled,
+}
+
+/// A planet event posted to one webhook, and how its attempts went.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub uuid: Uuid,
+    pub webhook: Uuid,
+    pub url: String,
+    pub tx: i64,
+    pub payload: String,
+    pub status: Status,
+    pub attempts: i64,
+    pub next_attempt: DateTime<Utc>,
+    pub last_status: Option<u16>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    secrets: BTreeMap<Uuid, String>,
+    deliveries: Vec<Delivery>,
+}
+
+/// Webhook secrets and deliveries, kept out of the store so that neither the
+/// secrets nor every attempt end up in the transaction log. Saved on every
+/// change to a file readable by its owner only.
+pub struct Outbox {
+    path: PathBuf,
+    saved: Saved,
+}
+
+impl Outbox {
+    /// Outbox persisted at `path`, a missing or unreadable file is an empty outbox.
+    pub fn open<P: AsRef<Path>>(path: P) -> Outbox {
+        let path = path.as_ref();
+        let saved = match load(path) {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!("could not load webhooks outbox from {}: \t {}", path.display(), e);
+                Saved::default()
+            }
+        };
+        Outbox{path: path.to_path_buf(), saved: saved}
+    }
+
+    pub fn secret(&self, webhook: Uuid) -> Option<&String> {
+        self.saved.secrets.get(&webhook)
+    }
+
+    pub fn set_secret(&mut self, webhook: Uuid, secret: String) -> Result<()> {
+        self.saved.secrets.insert(webhook, secret);
+        self.save()
+    }
+
+    /// Drops the secret of a deleted webhook, its pending deliveries are cancelled.
+    pub fn forget(&mut self, webhook: Uuid) -> Result<()> {
+        self.saved.secrets.remove(&webhook);
+        for d in self.saved.deliveries.iter_mut().filter(|d| d.webhook == webhook && d.status == Status::Pending) {
+            d.status = Status::Cancelled;
+        }
+        self.prune(webhook);
+        self.save()
+    }
+
+    /// Records a pending delivery of `payload`, due now.
+    pub fn enqueue(&mut self, webhook: Uuid, url: String, tx: i64, uuid: Uuid, payload: String) -> Result<Delivery> {
+        let d = Delivery{uuid: uuid, webhook: webhook, url: url, tx: tx, payload: payload, status: Status::Pending,
+                         attempts: 0, next_attempt: Utc::now(), last_status: None, last_error: None};
+        self.saved.deliveries.push(d.clone());
+        self.save()?;
+        Ok(d)
+    }
+
+    /// Pending deliveries whose next attempt is due at `now`.
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<Delivery> {
+        self.saved.deliveries.iter()
+            .filter(|d| d.status == Status::Pending && d.next_attempt <= now)
+            .cloned()
+            .collect()
+    }
+
+    /// Records an attempt, the response status when one was received and the
+    /// error when it was not a success. Failures are retried with a backoff
+    /// until `MAX_ATTEMPTS`.
+    pub fn attempted(&mut self, uuid: Uuid, status: Option<u16>, error: Option<String>) -> Result<Delivery> {
+        let d = {
+            let d = self.saved.deliveries.iter_mut().find(|d| d.uuid == uuid)
+                .ok_or_else(|| Error::from_kind(ErrorKind::NotFound(format!("delivery not found: {}", uuid))))?;
+            d.attempts += 1;
+            d.status = match error {
+                None => Status::Delivered,
+                Some(_) if d.status == Status::Cancelled => Status::Cancelled,
+                Some(_) if d.attempts >= MAX_ATTEMPTS => Status::Failed,
+                Some(_) => Status::Pending,
+            };
+            d.next_attempt = Utc::now() + backoff(d.attempts);
+            d.last_status = status.or(d.last_status);
+            if error.is_some() {
+                d.last_error = error;
+            }
+            d.clone()
+        };
+        self.prune(d.webhook);
+        self.save()?;
+        Ok(d)
+    }
+
+    /// Deliveries of a webhook latest first, `None` for an unknown webhook.
+    pub fn deliveries(&self, webhook: Uuid) -> Option<Vec<&Delivery>> {
+        if !self.saved.secrets.contains_key(&webhook) {
+            return None;
+        }
+        let mut out : Vec<&Delivery> = self.saved.deliveries.iter().filter(|d| d.webhook == webhook).collect();
+        out.sort_by(|a, b| b.tx.cmp(&a.tx));
+        Some(out)
+    }
+
+    /// Keeps the last `MAX_FINISHED` finished deliveries of a webhook, forgetting
+    /// them altogether once the webhook is deleted and nothing is pending.
+    fn prune(&mut self, webhook: Uuid) {
+        let keep = if self.saved.secrets.contains_key(&webhook) { MAX_FINISHED } else { 0 };
+        let finished = self.saved.deliveries.iter()
+            .filter(|d| d.webhook == webhook && d.status != Status::Pending)
+            .count();
+        let mut drop = finished.saturating_sub(keep);
+        // deliveries are appended, the oldest ones come first
+        self.saved.deliveries.retain(|d| {
+            if drop > 0 && d.webhook == webhook && d.status != Status::Pending {
+                drop -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Writes through a temporary file renamed over the outbox, created
+    /// readable by its owner only as it holds the webhook secrets.
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        serde_json::to_writer(private(&tmp)?, &self.saved)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn private(path: &Path) -> Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let _ = fs::remove_file(path);
+    Ok(OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?)
+}
+
+#[cfg(not(unix))]
+fn private(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().write(true).create(true).truncate(true).open(path)?)
+}
+
+fn load(path: &Path) -> Result<Saved> {
+    if !path.exists() {
+        return Ok(Saved::default());
+    }
+    let saved : Saved = serde_json::from_reader(File::open(path)?)?;
+    info!("loaded {} webhook secrets and {} deliveries from {}", saved.secrets.len(), saved.deliveries.len(), path.display());
+    Ok(saved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn outbox(name: &str) -> Outbox {
+        let path = env::temp_dir().join(format!("swapi-outbox-{}-{}.json", name, Uuid::new_v4().simple()));
+        Outbox::open(path)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_cap() {
+        let delays : Vec<i64> = (1..6).map(|n| backoff(n).num_seconds()).collect();
+        assert_eq!(delays, vec![2, 4, 8, 16, 32]);
+        assert_eq!(backoff(11).num_seconds(), 2048);
+        assert_eq!(backoff(12).num_seconds(), BACKOFF_MAX_SECS);
+        assert_eq!(backoff(1000).num_seconds(), BACKOFF_MAX_SECS);
+    }
+
+    #[test]
+    fn failed_attempts_are_retried_then_given_up() {
+        let mut outbox = outbox("retry");
+        let webhook = Uuid::new_v4();
+        outbox.set_secret(webhook, String::from("s3cr3t")).unwrap();
+        let d = outbox.enqueue(webhook, String::from("http://localhost/hook"), 1, Uuid::new_v4(), String::from("{}")).unwrap();
+        assert_eq!(outbox.due(Utc::now()).len(), 1);
+
+        let retried = outbox.attempted(d.uuid, Some(500), Some(String::from("status 500"))).unwrap();
+        assert_eq!((retried.status, retried.attempts, retried.last_status), (Status::Pending, 1, Some(500)));
+        assert!(outbox.due(Utc::now()).is_empty());
+        assert_eq!(outbox.due(Utc::now() + backoff(1)).len(), 1);
+
+        for _ in 1..MAX_ATTEMPTS {
+            outbox.attempted(d.uuid, None, Some(String::from("refused"))).unwrap();
+        }
+        let failed = &outbox.deliveries(webhook).unwrap()[0];
+        assert_eq!((failed.status, failed.attempts), (Status::Failed, MAX_ATTEMPTS));
+        assert_eq!(failed.last_error.as_ref().map(|e| e.as_str()), Some("refused"));
+        let _ = fs::remove_file(&outbox.path);
+    }
+
+    #[test]
+    fn survives_a_reopen_without_exposing_secrets() {
+        let mut outbox = outbox("reopen");
+        let webhook = Uuid::new_v4();
+        outbox.set_secret(webhook, String::from("s3cr3t")).unwrap();
+        outbox.enqueue(webhook, String::from("http://localhost/hook"), 1, Uuid::new_v4(), String::from("{}")).unwrap();
+
+        let reopened = Outbox::open(&outbox.path);
+        assert_eq!(reopened.secret(webhook).map(|s| s.as_str()), Some("s3cr3t"));
+        assert_eq!(reopened.due(Utc::now()).len(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&outbox.path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(&outbox.path);
+    }
+
+    #[test]
+    fn forgets_deleted_webhooks() {
+        let mut outbox = outbox("forget");
+        let webhook = Uuid::new_v4();
+        outbox.set_secret(webhook, String::from("s3cr3t")).unwrap();
+        for tx in 0..(MAX_FINISHED as i64 + 5) {
+            let d = outbox.enqueue(webhook, String::from("http://localhost/hook"), tx, Uuid::new_v4(), String::from("{}")).unwrap();
+            outbox.attempted(d.uuid, Some(200), None).unwrap();
+        }
+        assert_eq!(outbox.deliveries(webhook).unwrap().len(), MAX_FINISHED);
+        outbox.enqueue(webhook, String::from("http://localhost/hook"), 1000, Uuid::new_v4(), String::from("{}")).unwrap();
+
+        outbox.forget(webhook).unwrap();
+        assert_eq!(outbox.secret(webhook), None);
+        assert!(outbox.deliveries(webhook).is_none());
+        assert!(outbox.due(Utc::now()).is_empty());
+        let _ = fs::remove_file(&outbox.path);
+    }
+}
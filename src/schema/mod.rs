@@ -0,0 +1,130 @@
+use errors::Result;
+use mentat::{Store, Queryable, IntoResult};
+use domain::resources::{Resource, FieldType, Person, Film, Starship, Vehicle, Species, RELATIONS};
+
+const PLANET: &'static str = "[
+                  {:db/ident :planet/uuid
+                   :db/valueType :db.type/uuid
+                   :db/index true
+                   :db/unique :db.unique/identity
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :planet/name
+                   :db/valueType :db.type/string
+                   :db/index true
+                   :db/unique :db.unique/value
+                   :db/fulltext true
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :planet/climate
+                   :db/valueType :db.type/string
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :planet/terrain
+                   :db/valueType :db.type/string
+                   :db/cardinality :db.cardinality/one}
+                 ]";
+
+/// Webhook secrets and deliveries are kept in the outbox file now, their
+/// attributes remain for the data of earlier versions.
+const WEBHOOK: &'static str = "[
+                  {:db/ident :webhook/uuid
+                   :db/valueType :db.type/uuid
+                   :db/unique :db.unique/identity
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :webhook/url
+                   :db/valueType :db.type/string
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :webhook/secret
+                   :db/valueType :db.type/string
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/uuid
+                   :db/valueType :db.type/uuid
+                   :db/unique :db.unique/identity
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/webhook
+                   :db/valueType :db.type/ref
+                   :db/index true
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/tx
+                   :db/valueType :db.type/long
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/payload
+                   :db/valueType :db.type/string
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/status
+                   :db/valueType :db.type/string
+                   :db/index true
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/attempts
+                   :db/valueType :db.type/long
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/next_attempt
+                   :db/valueType :db.type/instant
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/last_status
+                   :db/valueType :db.type/long
+                   :db/cardinality :db.cardinality/one}
+                  {:db/ident :delivery/last_error
+                   :db/valueType :db.type/string
+                   :db/cardinality :db.cardinality/one}
+                 ]";
+
+/// Schema of a resource namespace, the uuid identity plus its fields with the
+/// name attribute indexed for fulltext search.
+fn resource<T: Resource>() -> String {
+    let ns = T::NAMESPACE;
+    let mut edn = format!("[{{:db/ident :{}/uuid
+                   :db/valueType :db.type/uuid
+                   :db/index true
+                   :db/unique :db.unique/identity
+                   :db/cardinality :db.cardinality/one}}", ns);
+    for &(field, ty) in T::FIELDS {
+        let value_type = match ty {
+            FieldType::Text => ":db.type/string",
+            FieldType::Long => ":db.type/long",
+        };
+        let name = if field == T::NAME { ":db/index true :db/fulltext true" } else { "" };
+        edn.push_str(&format!("
+                  {{:db/ident :{}/{}
+                   :db/valueType {} {}
+                   :db/cardinality :db.cardinality/one}}", ns, field, value_type, name));
+    }
+    edn.push_str("]");
+    edn
+}
+
+/// Ref typed attributes linking entities across namespaces.
+fn relations() -> String {
+    let mut edn = String::from("[");
+    for rel in RELATIONS {
+        let cardinality = if rel.many { ":db.cardinality/many" } else { ":db.cardinality/one" };
+        edn.push_str(&format!("
+                  {{:db/ident {}
+                   :db/valueType :db.type/ref
+                   :db/cardinality {}}}", rel.attribute(), cardinality));
+    }
+    edn.push_str("]");
+    edn
+}
+
+/// Transacts a schema group unless its marker attribute is already known to the store.
+fn install(store: &mut Store, marker: &str, edn: &str) -> Result<()> {
+    let known = store.q_once(&format!("[:find ?e . :where [?e :db/ident {}]]", marker), None)
+        .into_scalar_result()?;
+    if known.is_none() {
+        info!("installing schema for {}", marker);
+        let _ = store.transact(edn)?;
+    }
+    Ok(())
+}
+
+/// Brings the store schema up to date, new attributes are added to existing stores.
+pub fn ensure(store: &mut Store) -> Result<()> {
+    install(store, ":planet/uuid", PLANET)?;
+    install(store, ":person/uuid", &resource::<Person>())?;
+    install(store, ":film/uuid", &resource::<Film>())?;
+    install(store, ":starship/uuid", &resource::<Starship>())?;
+    install(store, ":vehicle/uuid", &resource::<Vehicle>())?;
+    install(store, ":species/uuid", &resource::<Species>())?;
+    install(store, ":planet/films", &relations())?;
+    install(store, ":webhook/uuid", WEBHOOK)?;
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Local http server answering canned replies, standing in for upstreams and
+//! webhook receivers in tests.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Announces one more byte than the body and holds the connection, a
+    /// body that never finishes.
+    pub stall: bool,
+}
+
+impl Reply {
+    pub fn status(status: u16) -> Reply {
+        Reply{status: status, headers: Vec::new(), body: String::new(), stall: false}
+    }
+
+    pub fn json(body: &str) -> Reply {
+        Reply{status: 200, headers: vec![(String::from("Content-Type"), String::from("application/json"))],
+              body: String::from(body), stall: false}
+    }
+
+    pub fn stalled(mut self) -> Reply {
+        self.stall = true;
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path and query of the request line.
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        self.headers.iter().find(|&&(ref n, _)| *n == name).map(|&(_, ref v)| v.clone())
+    }
+}
+
+pub struct Server {
+    /// Base url, `http://127.0.0.1:<port>`.
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    /// Requests received so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Serves `replies` in order on a local port, the last one over and over
+/// once the others are used up.
+pub fn serve(replies: Vec<Reply>) -> Server {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+    thread::spawn(move || {
+        let mut served = 0;
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let request = match read(&stream) {
+                Some(request) => request,
+                None => continue,
+            };
+            received.lock().unwrap().push(request);
+            let reply = &replies[::std::cmp::min(served, replies.len() - 1)];
+            served += 1;
+            let mut head = format!("HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n",
+                                   reply.status, reply.body.len() + if reply.stall { 1 } else { 0 });
+            for &(ref name, ref value) in reply.headers.iter() {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            head.push_str("\r\n");
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(reply.body.as_bytes());
+            let _ = stream.flush();
+            if reply.stall {
+                thread::sleep(Duration::from_secs(2));
+            }
+        }
+    });
+    Server{url: url, requests: requests}
+}
+
+fn read(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = (String::from(parts.next()?), String::from(parts.next()?));
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_right();
+        if line.is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        headers.push((header.next()?.trim().to_lowercase(), String::from(header.next().unwrap_or("").trim())));
+    }
+    let length = headers.iter()
+        .find(|&&(ref n, _)| n == "content-length")
+        .and_then(|&(_, ref v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request{method: method, path: path, headers: headers, body: body})
+}
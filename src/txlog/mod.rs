@@ -0,0 +1,386 @@
+use chrono::{DateTime, Utc};
+use errors::{Result, Error, ErrorKind};
+use mentat::{TypedValue, Binding, Queryable, IntoResult, QueryInputs};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// First transaction id after the bootstrap schema.
+pub const TX0: i64 = 0x10000000;
+
+/// One assertion or retraction recorded in the transaction log.
+#[derive(Debug, Clone)]
+pub struct Datom {
+    pub e: i64,
+    pub a: String,
+    pub v: TypedValue,
+    pub tx: i64,
+    pub instant: DateTime<Utc>,
+    pub added: bool,
+}
+
+/// Transaction to read at, either its id or the latest one at an instant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    Tx(i64),
+    Instant(DateTime<Utc>),
+}
+
+impl AsOf {
+    /// Parses a transaction id or a RFC3339 instant.
+    pub fn parse(s: &str) -> Option<AsOf> {
+        match s.parse::<i64>() {
+            Ok(tx) => Some(AsOf::Tx(tx)),
+            Err(_) => DateTime::parse_from_rfc3339(s).ok().map(|t| AsOf::Instant(t.with_timezone(&Utc))),
+        }
+    }
+}
+
+/// Datoms of the transactions in `[from, to)` ordered by transaction, with
+/// attributes reported by ident.
+pub fn datoms<Q: Queryable>(q: &Q, from: i64, to: i64) -> Result<Vec<Datom>> {
+    logged(q, &format!("[:find ?e ?ident ?v ?tx ?instant ?added
+                         :where [(tx-ids $ {} {}) [?tx ...]]
+                                [(tx-data $ ?tx) [[?e ?a ?v _ ?added]]]
+                                [?a :db/ident ?ident]
+                                [?tx :db/txInstant ?instant]]", from, to), None)
+}
+
+/// Datoms of the attribute `a` over the whole log.
+pub fn of_attribute<Q: Queryable>(q: &Q, a: &str) -> Result<Vec<Datom>> {
+    logged(q, &format!("[:find ?e ?ident ?v ?tx ?instant ?added
+                         :where [(tx-ids $ {} {}) [?tx ...]]
+                                [(tx-data $ ?tx) [[?e ?a ?v _ ?added]]]
+                                [?a :db/ident ?ident]
+                                [?a :db/ident {}]
+                                [?tx :db/txInstant ?instant]]", TX0, i64::max_value(), a), None)
+}
+
+/// Datoms recorded after transaction `since`.
+pub fn since<Q: Queryable>(q: &Q, since: i64) -> Result<Vec<Datom>> {
+    datoms(q, since + 1, i64::max_value())
+}
+
+/// Datoms about entity `e` and datoms of other entities referring to it,
+/// the whole history of one entity without reading the rest of the log.
+pub fn of_entity<Q: Queryable>(q: &Q, e: i64) -> Result<Vec<Datom>> {
+    let mut out = logged(q, &format!("[:find ?e ?ident ?v ?tx ?instant ?added
+                                       :in ?e
+                                       :where [(tx-ids $ {} {}) [?tx ...]]
+                                              [(tx-data $ ?tx) [[?e ?a ?v _ ?added]]]
+                                              [?a :db/ident ?ident]
+                                              [?tx :db/txInstant ?instant]]", TX0, i64::max_value()),
+                         Some(QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))])))?;
+    out.extend(logged(q, &format!("[:find ?e ?ident ?v ?tx ?instant ?added
+                                    :in ?v
+                                    :where [(tx-ids $ {} {}) [?tx ...]]
+                                           [(tx-data $ ?tx) [[?e ?a ?v _ ?added]]]
+                                           [?a :db/ident ?ident]
+                                           [?tx :db/txInstant ?instant]]", TX0, i64::max_value()),
+                      Some(QueryInputs::with_value_sequence(vec![(var!(?v), TypedValue::Ref(e))])))?
+               .into_iter()
+               .filter(|d| d.e != e));
+    sort(&mut out);
+    Ok(out)
+}
+
+fn logged<Q: Queryable>(q: &Q, query: &str, inputs: Option<QueryInputs>) -> Result<Vec<Datom>> {
+    let mut out : Vec<Datom> = q.q_once(query, inputs)
+        .into_rel_result()?
+        .into_iter()
+        .filter_map(|row| {
+            let mut row = row.into_iter();
+            match (row.next(), row.next(), row.next(), row.next(), row.next(), row.next()) {
+                (Some(Binding::Scalar(TypedValue::Ref(e))), Some(Binding::Scalar(TypedValue::Keyword(a))),
+                 Some(Binding::Scalar(v)), Some(Binding::Scalar(TypedValue::Ref(tx))),
+                 Some(Binding::Scalar(TypedValue::Instant(instant))), Some(Binding::Scalar(TypedValue::Boolean(added)))) => {
+                    Some(Datom{e: e, a: format!("{}", a), v: v, tx: tx, instant: instant, added: added})
+                }
+                _ => {
+                    warn!("Incorrect query shape for tx log datoms.");
+                    None
+                }
+            }
+        })
+        .collect();
+    sort(&mut out);
+    Ok(out)
+}
+
+/// Retractions first so a value asserted again in the same transaction survives replay.
+fn sort(log: &mut Vec<Datom>) {
+    log.sort_by(|a, b| a.tx.cmp(&b.tx).then_with(|| a.added.cmp(&b.added)));
+}
+
+/// Transaction id an `AsOf` refers to, the latest transaction at or before an instant.
+pub fn resolve<Q: Queryable>(q: &Q, as_of: AsOf) -> Result<i64> {
+    match as_of {
+        AsOf::Tx(tx) => Ok(tx),
+        AsOf::Instant(t) => {
+            let rows = q.q_once(&format!("[:find ?tx ?instant
+                                           :in ?t
+                                           :where [(tx-ids $ {} {}) [?tx ...]]
+                                                  [?tx :db/txInstant ?instant]
+                                                  [(<= ?instant ?t)]
+                                           :order (desc ?instant) (desc ?tx)
+                                           :limit 1]", TX0, i64::max_value()),
+                                Some(QueryInputs::with_value_sequence(vec![(var!(?t), TypedValue::Instant(t))])))
+                .into_rel_result()?;
+            match rows.into_iter().next().and_then(|row| row.into_iter().next()) {
+                Some(Binding::Scalar(TypedValue::Ref(tx))) => Ok(tx),
+                _ => Err(Error::from_kind(ErrorKind::NotFound(format!("no transaction at or before {}", t)))),
+            }
+        }
+    }
+}
+
+/// Attribute values of every entity as of transaction `tx`.
+pub fn replay(log: &[Datom], tx: i64) -> BTreeMap<i64, Vec<(String, TypedValue)>> {
+    let mut entities : BTreeMap<i64, Vec<(String, TypedValue)>> = BTreeMap::new();
+    for d in log.iter().filter(|d| d.tx <= tx) {
+        let attrs = entities.entry(d.e).or_insert_with(Vec::new);
+        if d.added {
+            attrs.push((d.a.clone(), d.v.clone()));
+        } else if let Some(i) = attrs.iter().position(|&(ref a, ref v)| *a == d.a && *v == d.v) {
+            attrs.remove(i);
+        }
+    }
+    entities.into_iter().filter(|&(_, ref attrs)| !attrs.is_empty()).collect()
+}
+
+/// Attribute values of every entity right before its identity attribute `a` was
+/// last retracted, in a single replay of the log.
+pub fn deleted_states(log: &[Datom], a: &str) -> BTreeMap<i64, Vec<(String, TypedValue)>> {
+    let mut entities : BTreeMap<i64, Vec<(String, TypedValue)>> = BTreeMap::new();
+    // entities as they were before the transaction being replayed touched them
+    let mut touched : BTreeMap<i64, Vec<(String, TypedValue)>> = BTreeMap::new();
+    let mut out = BTreeMap::new();
+    let mut tx = None;
+    for d in log.iter() {
+        if tx != Some(d.tx) {
+            tx = Some(d.tx);
+            touched.clear();
+        }
+        let attrs = entities.entry(d.e).or_insert_with(Vec::new);
+        if !touched.contains_key(&d.e) {
+            touched.insert(d.e, attrs.clone());
+        }
+        if d.added {
+            attrs.push((d.a.clone(), d.v.clone()));
+        } else {
+            if d.a == a {
+                out.insert(d.e, touched[&d.e].clone());
+            }
+            if let Some(i) = attrs.iter().position(|&(ref x, ref v)| *x == d.a && *v == d.v) {
+                attrs.remove(i);
+            }
+        }
+    }
+    out
+}
+
+/// Value of attribute `a` in a replayed entity.
+pub fn value<'a>(attrs: &'a [(String, TypedValue)], a: &str) -> Option<&'a TypedValue> {
+    attrs.iter().find(|&&(ref attr, _)| attr == a).map(|&(_, ref v)| v)
+}
+
+/// Entity that held `uuid` under the identity attribute `a` at any point of the log,
+/// retracted entities included.
+pub fn entity<Q: Queryable>(q: &Q, a: &str, uuid: Uuid) -> Result<Option<i64>> {
+    let rows = q.q_once(&format!("[:find ?e
+                                   :in ?u
+                                   :where [(tx-ids $ {} {}) [?tx ...]]
+                                          [(tx-data $ ?tx) [[?e ?a ?u _ _]]]
+                                          [?a :db/ident {}]
+                                   :limit 1]", TX0, i64::max_value(), a),
+                        Some(QueryInputs::with_value_sequence(vec![(var!(?u), TypedValue::Uuid(uuid))])))
+        .into_rel_result()?;
+    match rows.into_iter().next().and_then(|row| row.into_iter().next()) {
+        Some(Binding::Scalar(TypedValue::Ref(e))) => Ok(Some(e)),
+        _ => Ok(None),
+    }
+}
+
+/// Last uuid logged for entity `e`, whatever its namespace.
+pub fn uuid_of<Q: Queryable>(q: &Q, e: i64) -> Result<Option<Uuid>> {
+    let rows = q.q_once(&format!("[:find ?u ?tx
+                                   :in ?e
+                                   :where [(tx-ids $ {} {}) [?tx ...]]
+                                          [(tx-data $ ?tx) [[?e ?a ?u _ _]]]
+                                          [?a :db/valueType :db.type/uuid]
+                                   :order (desc ?tx)
+                                   :limit 1]", TX0, i64::max_value()),
+                        Some(QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))])))
+        .into_rel_result()?;
+    match rows.into_iter().next().and_then(|row| row.into_iter().next()) {
+        Some(Binding::Scalar(TypedValue::Uuid(u))) => Ok(Some(u)),
+        _ => Ok(None),
+    }
+}
+
+/// Entities whose identity attribute `a` is currently retracted, with the
+/// transaction and instant of their deletion, latest first.
+pub fn deleted(log: &[Datom], a: &str) -> Vec<(i64, i64, DateTime<Utc>)> {
+    let mut entities : BTreeMap<i64, Option<(i64, DateTime<Utc>)>> = BTreeMap::new();
+    for d in log.iter().filter(|d| d.a == a) {
+        entities.insert(d.e, if d.added { None } else { Some((d.tx, d.instant)) });
+    }
+    let mut out : Vec<(i64, i64, DateTime<Utc>)> = entities.into_iter()
+        .filter_map(|(e, deletion)| deletion.map(|(tx, instant)| (e, tx, instant)))
+        .collect();
+    out.sort_by(|a, b| b.1.cmp(&a.1));
+    out
+}
+
+/// Kind of change a transaction made to an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Change::Created => "created",
+            Change::Updated => "updated",
+            Change::Deleted => "deleted",
+        }
+    }
+}
+
+/// Change made to an entity by one transaction.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub tx: i64,
+    pub instant: DateTime<Utc>,
+    pub e: i64,
+    pub uuid: Uuid,
+    pub change: Change,
+}
+
+/// Uuids held under the identity attribute `a` by the entities of the log.
+pub fn identities(log: &[Datom], a: &str) -> BTreeMap<i64, Uuid> {
+    let mut uuids : BTreeMap<i64, Uuid> = BTreeMap::new();
+    for d in log.iter().filter(|d| d.a == a) {
+        if let TypedValue::Uuid(u) = d.v {
+            uuids.insert(d.e, u);
+        }
+    }
+    uuids
+}
+
+/// Changes to the entities of `uuids` identified by the attribute `a`, in
+/// transactions after `since` and in log order. Asserting or retracting the
+/// identity is a creation or a deletion, any other datom is an update.
+pub fn events_of(log: &[Datom], uuids: &BTreeMap<i64, Uuid>, a: &str, since: i64) -> Vec<Event> {
+    let mut out : BTreeMap<(i64, i64), Event> = BTreeMap::new();
+    for d in log.iter().filter(|d| d.tx > since) {
+        let uuid = match uuids.get(&d.e) {
+            Some(u) => *u,
+            None => continue,
+        };
+        let change = match (d.a == a, d.added) {
+            (true, true) => Change::Created,
+            (true, false) => Change::Deleted,
+            _ => Change::Updated,
+        };
+        let event = out.entry((d.tx, d.e))
+            .or_insert_with(|| Event{tx: d.tx, instant: d.instant, e: d.e, uuid: uuid, change: change});
+        if event.change == Change::Updated {
+            event.change = change;
+        }
+    }
+    out.into_iter().map(|(_, event)| event).collect()
+}
+
+/// Latest transaction recorded in the log, `None` for an empty log.
+pub fn latest(log: &[Datom]) -> Option<i64> {
+    log.iter().map(|d| d.tx).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn datom(e: i64, a: &str, v: TypedValue, tx: i64, added: bool) -> Datom {
+        Datom{e: e, a: String::from(a), v: v, tx: tx, instant: Utc.timestamp(tx - TX0, 0), added: added}
+    }
+
+    fn text(s: &str) -> TypedValue {
+        TypedValue::from(String::from(s))
+    }
+
+    fn log(uuid: Uuid) -> Vec<Datom> {
+        vec![
+            datom(100, ":planet/uuid", TypedValue::Uuid(uuid), TX0 + 1, true),
+            datom(100, ":planet/name", text("Hoth"), TX0 + 1, true),
+            datom(100, ":planet/name", text("Hoth"), TX0 + 2, false),
+            datom(100, ":planet/name", text("Echo Base"), TX0 + 2, true),
+            datom(200, ":person/name", text("Luke"), TX0 + 3, true),
+            datom(100, ":planet/uuid", TypedValue::Uuid(uuid), TX0 + 4, false),
+            datom(100, ":planet/name", text("Echo Base"), TX0 + 4, false),
+        ]
+    }
+
+    #[test]
+    fn replays_values_as_of_a_transaction() {
+        let uuid = Uuid::new_v4();
+        let log = log(uuid);
+        assert_eq!(value(&replay(&log, TX0 + 1)[&100], ":planet/name"), Some(&text("Hoth")));
+        assert_eq!(value(&replay(&log, TX0 + 3)[&100], ":planet/name"), Some(&text("Echo Base")));
+        assert!(!replay(&log, TX0 + 4).contains_key(&100));
+        assert!(replay(&log, TX0).is_empty());
+    }
+
+    #[test]
+    fn reasserted_values_survive_replay() {
+        let mut log = vec![datom(1, ":planet/name", text("Hoth"), TX0 + 1, true),
+                           datom(1, ":planet/name", text("Hoth"), TX0 + 2, true),
+                           datom(1, ":planet/name", text("Hoth"), TX0 + 2, false)];
+        sort(&mut log);
+        assert_eq!(value(&replay(&log, TX0 + 2)[&1], ":planet/name"), Some(&text("Hoth")));
+    }
+
+    #[test]
+    fn classifies_events_by_identity() {
+        let uuid = Uuid::new_v4();
+        let log = log(uuid);
+        let uuids = identities(&log, ":planet/uuid");
+        assert_eq!(uuids.get(&100), Some(&uuid));
+        assert!(!uuids.contains_key(&200));
+
+        let changes : Vec<(i64, Change)> = events_of(&log, &uuids, ":planet/uuid", 0).iter()
+            .map(|e| (e.tx, e.change))
+            .collect();
+        assert_eq!(changes, vec![(TX0 + 1, Change::Created), (TX0 + 2, Change::Updated), (TX0 + 4, Change::Deleted)]);
+        assert_eq!(events_of(&log, &uuids, ":planet/uuid", TX0 + 2).len(), 1);
+    }
+
+    #[test]
+    fn finds_deletions_and_latest() {
+        let log = log(Uuid::new_v4());
+        let deletions : Vec<(i64, i64)> = deleted(&log, ":planet/uuid").iter().map(|d| (d.0, d.1)).collect();
+        assert_eq!(deletions, vec![(100, TX0 + 4)]);
+        assert_eq!(latest(&log), Some(TX0 + 4));
+        assert_eq!(latest(&[]), None);
+    }
+
+    #[test]
+    fn keeps_states_before_deletion() {
+        let log = log(Uuid::new_v4());
+        let states = deleted_states(&log, ":planet/uuid");
+        assert_eq!(states.len(), 1);
+        assert_eq!(value(&states[&100], ":planet/name"), Some(&text("Echo Base")));
+        assert!(value(&states[&100], ":planet/uuid").is_some());
+    }
+
+    #[test]
+    fn parses_as_of() {
+        assert_eq!(AsOf::parse("268435470"), Some(AsOf::Tx(268435470)));
+        assert_eq!(AsOf::parse("2018-05-10T10:00:00Z"), Some(AsOf::Instant(Utc.ymd(2018, 5, 10).and_hms(10, 0, 0))));
+        assert_eq!(AsOf::parse("yesterday"), None);
+    }
+}
@@ -0,0 +1,190 @@
+use errors::Result;
+use serde_json::value::{Value, Map};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use super::{records, StaticProvider};
+
+/// Key other records refer to a record by, its `url` in api dumps or its
+/// primary key in fixture dumps.
+fn key(v: &Value) -> Option<String> {
+    match *v {
+        Value::String(ref s) => Some(s.clone()),
+        Value::Number(ref n) => Some(format!("{}", n)),
+        _ => None,
+    }
+}
+
+fn keys(record: &Map<String, Value>, field: &str) -> Vec<String> {
+    match record.get(field) {
+        Some(&Value::Array(ref refs)) => refs.iter().filter_map(key).collect(),
+        Some(v) => key(v).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Records of `<dir>/<file>.json` with their key, SWAPI api objects and
+/// fixtures `{"model", "pk", "fields"}` alike.
+fn load(dir: &Path, file: &str) -> Result<Vec<(String, Map<String, Value>)>> {
+    Ok(records(dir.join(format!("{}.json", file)))?
+        .into_iter()
+        .filter_map(|record| match record {
+            Value::Object(mut record) => {
+                let id = match record.get("pk").or_else(|| record.get("url")).and_then(key) {
+                    Some(id) => id,
+                    None => return None,
+                };
+                match record.remove("fields") {
+                    Some(Value::Object(fields)) => Some((id, fields)),
+                    _ => Some((id, record)),
+                }
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Loads a local SWAPI dump directory with `planets.json`, `films.json` and
+/// optionally `people.json`. A planet films are the ones it lists plus the
+/// films listing it, its residents the people whose homeworld it is, so the
+/// planets searched have the shape of an api response whichever side of the
+/// relations the dump records.
+pub fn open<P: AsRef<Path>>(dir: P) -> Result<StaticProvider> {
+    let dir = dir.as_ref();
+    let planets = load(dir, "planets")?;
+    let films = load(dir, "films")?;
+    let people = if dir.join("people.json").exists() { load(dir, "people")? } else { Vec::new() };
+
+    let mut planet_films : BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for &(ref film, ref record) in films.iter() {
+        for planet in keys(record, "planets") {
+            planet_films.entry(planet).or_insert_with(BTreeSet::new).insert(film.clone());
+        }
+    }
+    let mut residents : BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for &(ref person, ref record) in people.iter() {
+        for planet in keys(record, "homeworld") {
+            residents.entry(planet).or_insert_with(Vec::new).push(person.clone());
+        }
+    }
+
+    let results : Vec<Value> = planets.iter()
+        .map(|&(ref id, ref record)| {
+            let mut planet = record.clone();
+            let mut listed : BTreeSet<String> = keys(record, "films").into_iter().collect();
+            if let Some(films) = planet_films.get(id) {
+                listed.extend(films.iter().cloned());
+            }
+            planet.insert(String::from("films"), json!(listed));
+            if !people.is_empty() {
+                planet.insert(String::from("residents"), json!(residents.get(id).cloned().unwrap_or_default()));
+            }
+            planet.insert(String::from("url"), json!(id));
+            Value::Object(planet)
+        })
+        .collect();
+    info!("loaded {} films and {} people from {}", films.len(), people.len(), dir.display());
+    Ok(StaticProvider::new(format!("{}", dir.display()), results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use serde_json;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use upstream::FilmsProvider;
+    use uuid::Uuid;
+
+    fn dump(files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("swapi-dump-{}", Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        for &(name, json) in files {
+            File::create(dir.join(name)).unwrap().write_all(json.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    /// Films and residents of the planet found searching `name`.
+    fn planet(provider: &StaticProvider, name: &str) -> (usize, Option<usize>) {
+        let body : Value = serde_json::from_slice(&provider.search(name).wait().unwrap().body).unwrap();
+        let planet = &body["results"][0];
+        (planet["films"].as_array().unwrap().len(), planet.get("residents").and_then(|r| r.as_array()).map(|r| r.len()))
+    }
+
+    #[test]
+    fn joins_api_dumps() {
+        let dir = dump(&[
+            ("planets.json", r#"{"count": 2, "next": null, "results": [
+                {"name": "Tatooine", "url": "https://swapi.dev/api/planets/1/", "films": ["https://swapi.dev/api/films/1/"]},
+                {"name": "Alderaan", "url": "https://swapi.dev/api/planets/2/", "films": []}]}"#),
+            ("films.json", r#"{"results": [
+                {"title": "A New Hope", "url": "https://swapi.dev/api/films/1/", "planets": ["https://swapi.dev/api/planets/1/", "https://swapi.dev/api/planets/2/"]},
+                {"title": "Return of the Jedi", "url": "https://swapi.dev/api/films/3/", "planets": ["https://swapi.dev/api/planets/1/"]}]}"#),
+            ("people.json", r#"[{"name": "Luke Skywalker", "url": "https://swapi.dev/api/people/1/", "homeworld": "https://swapi.dev/api/planets/1/"},
+                               {"name": "Leia Organa", "url": "https://swapi.dev/api/people/5/", "homeworld": "https://swapi.dev/api/planets/2/"},
+                               {"name": "Owen Lars", "url": "https://swapi.dev/api/people/6/", "homeworld": "https://swapi.dev/api/planets/1/"}]"#),
+        ]);
+        let provider = open(&dir).unwrap();
+        // listed by the planet and by the film count once
+        assert_eq!(planet(&provider, "Tatooine"), (2, Some(2)));
+        assert_eq!(planet(&provider, "Alderaan"), (1, Some(1)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn joins_fixture_dumps() {
+        let dir = dump(&[
+            ("planets.json", r#"[{"model": "resources.planet", "pk": 1, "fields": {"name": "Tatooine"}},
+                                 {"model": "resources.planet", "pk": 2, "fields": {"name": "Hoth"}}]"#),
+            ("films.json", r#"[{"model": "resources.film", "pk": 1, "fields": {"title": "A New Hope", "planets": [1]}},
+                               {"model": "resources.film", "pk": 2, "fields": {"title": "The Empire Strikes Back", "planets": [2]}},
+                               {"model": "resources.film", "pk": 3, "fields": {"title": "Return of the Jedi", "planets": [1]}}]"#),
+            ("people.json", r#"[{"model": "resources.people", "pk": 1, "fields": {"name": "Luke Skywalker", "homeworld": 1}}]"#),
+        ]);
+        let provider = open(&dir).unwrap();
+        assert_eq!(planet(&provider, "Tatooine"), (2, Some(1)));
+        assert_eq!(planet(&provider, "Hoth"), (1, Some(0)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn people_are_optional() {
+        let dir = dump(&[
+            ("planets.json", r#"[{"model": "resources.planet", "pk": 1, "fields": {"name": "Hoth"}}]"#),
+            ("films.json", r#"[{"model": "resources.film", "pk": 2, "fields": {"planets": [1]}}]"#),
+        ]);
+        assert_eq!(planet(&open(&dir).unwrap(), "Hoth"), (1, None));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn skips_records_without_a_key() {
+        let dir = dump(&[
+            ("planets.json", r#"[{"model": "resources.planet", "pk": 1, "fields": {"name": "Hoth"}},
+                                 {"name": "Nowhere"}, "junk", 42]"#),
+            ("films.json", r#"[{"model": "resources.film", "pk": 2, "fields": {"planets": [1, 99]}}]"#),
+        ]);
+        let provider = open(&dir).unwrap();
+        assert_eq!(planet(&provider, "Hoth"), (1, None));
+        let body : Value = serde_json::from_slice(&provider.search("Nowhere").wait().unwrap().body).unwrap();
+        assert_eq!(body["count"], 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fails_on_missing_or_malformed_files() {
+        let planets = r#"[{"model": "resources.planet", "pk": 1, "fields": {"name": "Hoth"}}]"#;
+        let missing = dump(&[("planets.json", planets)]);
+        assert!(open(&missing).is_err());
+        let malformed = dump(&[("planets.json", planets), ("films.json", r#"{"films": []}"#)]);
+        assert!(open(&malformed).is_err());
+        let truncated = dump(&[("planets.json", planets), ("films.json", r#"[{"pk": 1"#)]);
+        assert!(open(&truncated).is_err());
+        for dir in [missing, malformed, truncated].iter() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
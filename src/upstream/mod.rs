@@ -0,0 +1,269 @@
+use actix::Arbiter;
+use actix_web::{client, http::header};
+use domain::films_key;
+use errors::{Result, Error, ErrorKind};
+use futures::{Future, Stream, future::{self, Either}};
+use percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
+use serde_json::{self, value::Value};
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+use tokio_core::reactor::Timeout;
+
+pub mod dump;
+pub mod resilience;
+
+define_encode_set! {
+    /// Characters escaped in the search term of an upstream query, on top of
+    /// the ones invalid in any query.
+    pub SEARCH_ENCODE_SET = [QUERY_ENCODE_SET] | {'&', '+', '=', '%', ';', '/', '?'}
+}
+
+/// Body of an upstream response, with how long it may be cached when
+/// upstream tells.
+pub struct Response {
+    pub body: Vec<u8>,
+    pub max_age: Option<Duration>,
+}
+
+impl Response {
+    pub fn new(body: Vec<u8>) -> Response {
+        Response{body: body, max_age: None}
+    }
+}
+
+/// Time a response may be cached per its `Cache-Control` header, none when
+/// upstream does not tell.
+fn max_age(response: &client::ClientResponse) -> Option<Duration> {
+    response.headers().get(header::CACHE_CONTROL)
+        .and_then(|h| h.to_str().ok())
+        .and_then(max_age_of)
+}
+
+/// Time a `Cache-Control` header value lets a response be cached.
+fn max_age_of(cache_control: &str) -> Option<Duration> {
+    let cache_control = cache_control.to_lowercase();
+    for directive in cache_control.split(',').map(|d| d.trim()) {
+        if directive == "no-store" || directive == "no-cache" {
+            return Some(Duration::from_secs(0));
+        }
+        if directive.starts_with("max-age=") {
+            if let Ok(secs) = directive["max-age=".len()..].trim_matches('"').parse::<u64>() {
+                return Some(Duration::from_secs(secs));
+            }
+        }
+    }
+    None
+}
+
+/// Fails as an upstream timeout once `timeout` is past, raced against a
+/// request so no part of it outlives the timeout.
+pub fn expiry<T: 'static>(timeout: Duration, what: String) -> Box<Future<Item=T, Error=Error>> {
+    Box::new(future::result(Timeout::new(timeout, Arbiter::handle()))
+        .and_then(|expired| expired)
+        .map_err(Error::from)
+        .and_then(move |_| future::err(Error::from_kind(ErrorKind::UpstreamTimeout(what)))))
+}
+
+/// Source of SWAPI planet searches the films counts are resolved from. Every
+/// provider answers with the body of a SWAPI `planets/?search=` response, so
+/// `InboundCacheProcessor` parses them all the same way.
+pub trait FilmsProvider: Send + Sync {
+    /// Where the films come from, reported in logs.
+    fn source(&self) -> String;
+
+    /// Body of the planets search response for `name`.
+    fn search(&self, name: &str) -> Box<Future<Item=Response, Error=Error>>;
+
+    /// Body of the page a search response links as `next`. Providers answering
+    /// every search in one page have no further pages to fetch.
+    fn next(&self, url: &str) -> Box<Future<Item=Response, Error=Error>> {
+        Box::new(future::err(Error::from_kind(ErrorKind::Upstream(
+            format!("{} has no page {}", self.source(), url)))))
+    }
+}
+
+/// SWAPI compatible http api, `https://swapi.dev/api` or a local mock.
+pub struct HttpProvider {
+    base_url: String,
+    timeout: Duration,
+}
+
+impl HttpProvider {
+    pub fn new(base_url: &str, timeout: Duration) -> HttpProvider {
+        HttpProvider{base_url: String::from(base_url.trim_right_matches('/')), timeout: timeout}
+    }
+
+    fn get(&self, url: String) -> Box<Future<Item=Response, Error=Error>> {
+        let req = match client::get(url)   // <- Create request builder
+                .header("User-Agent", "Actix-web")
+                .finish() {
+            Ok(req) => req,
+            Err(e) => return Box::new(future::err(Error::from_kind(ErrorKind::Upstream(format!("{}", e))))),
+        };
+        let what = format!("upstream request not done in {:?}", self.timeout);
+        Box::new(req.send()                               // <- Send http request
+            .timeout(self.timeout)
+            .map_err(|e| {
+                warn!("got error 1: \t {}", e);
+                let kind = match e {
+                    client::SendRequestError::Timeout => ErrorKind::UpstreamTimeout(format!("{}", e)),
+                    _ => ErrorKind::Upstream(format!("{}", e)),
+                };
+                Error::from_kind(kind)
+            })
+            .and_then(|response| {               // <- server http response
+                if !response.status().is_success() {
+                    // error pages are no search results, retried like failed requests
+                    return Either::A(future::err(Error::from_kind(ErrorKind::Upstream(
+                        format!("status {}", response.status())))));
+                }
+                let max_age = max_age(&response);
+                Either::B(response.concat2()     // <- get Body future
+                        .map_err(|e| {
+                            warn!("got error 2: \t {}", e);
+                            Error::from_kind(ErrorKind::Upstream(format!("{}", e)))
+                        })
+                        .map(move |body| Response{body: body.to_vec(), max_age: max_age}))
+            })
+            // the body read too, `timeout` above only bounds the response head
+            .select(expiry(self.timeout, what))
+            .map(|(response, _)| response)
+            .map_err(|(e, _)| e))
+    }
+}
+
+impl FilmsProvider for HttpProvider {
+    fn source(&self) -> String {
+        self.base_url.clone()
+    }
+
+    fn search(&self, name: &str) -> Box<Future<Item=Response, Error=Error>> {
+        self.get(format!("{}/planets/?search={}", self.base_url, utf8_percent_encode(name, SEARCH_ENCODE_SET)))
+    }
+
+    fn next(&self, url: &str) -> Box<Future<Item=Response, Error=Error>> {
+        if !within(&self.base_url, url) {
+            return Box::new(future::err(Error::from_kind(ErrorKind::Upstream(
+                format!("page {} is outside {}", url, self.base_url)))));
+        }
+        self.get(String::from(url))
+    }
+}
+
+/// Whether `url` is `base` or below it, so that a `next` link can't send
+/// lookups to another host.
+fn within(base: &str, url: &str) -> bool {
+    url.starts_with(base) && match url[base.len()..].chars().next() {
+        None | Some('/') | Some('?') => true,
+        _ => false,
+    }
+}
+
+/// Planets of a local json file, either a SWAPI planets page or an array of
+/// planets, searched by name like SWAPI does.
+pub struct StaticProvider {
+    path: String,
+    planets: Vec<Value>,
+}
+
+/// Records of a json file, either a SWAPI page with `results` or an array.
+pub fn records<P: AsRef<Path>>(path: P) -> Result<Vec<Value>> {
+    let json : Value = serde_json::from_reader(File::open(path.as_ref())?)?;
+    match json {
+        Value::Array(records) => Ok(records),
+        Value::Object(mut page) => match page.remove("results") {
+            Some(Value::Array(records)) => Ok(records),
+            _ => Err(Error::from_kind(ErrorKind::Msg(
+                format!("no results in {}", path.as_ref().display())))),
+        },
+        _ => Err(Error::from_kind(ErrorKind::Msg(
+            format!("unexpected json in {}", path.as_ref().display())))),
+    }
+}
+
+impl StaticProvider {
+    pub fn new(source: String, planets: Vec<Value>) -> StaticProvider {
+        info!("loaded {} planets from {}", planets.len(), source);
+        StaticProvider{path: source, planets: planets}
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<StaticProvider> {
+        let planets = records(path.as_ref())?;
+        Ok(StaticProvider::new(format!("{}", path.as_ref().display()), planets))
+    }
+}
+
+impl FilmsProvider for StaticProvider {
+    fn source(&self) -> String {
+        self.path.clone()
+    }
+
+    /// Planets whose name contains `name`, compared by films key like the
+    /// films count is resolved.
+    fn search(&self, name: &str) -> Box<Future<Item=Response, Error=Error>> {
+        let key = films_key(name);
+        let results : Vec<&Value> = self.planets.iter()
+            .filter(|p| p.get("name").and_then(|n| n.as_str()).map_or(false, |n| films_key(n).contains(&key)))
+            .collect();
+        Box::new(future::result(serde_json::to_vec(&json!({
+            "count": results.len(),
+            "next": Value::Null,
+            "previous": Value::Null,
+            "results": results,
+        })).map(Response::new).map_err(Into::into)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_out_on_a_slow_body() {
+        use actix::System;
+        use stub;
+        let server = stub::serve(vec![stub::Reply::json(r#"{"next":null,"results":[]}"#).stalled()]);
+        let provider = HttpProvider::new(&server.url, Duration::from_millis(300));
+        match System::new("upstream").run_until_complete(provider.search("Hoth")) {
+            Err(e) => match *e.kind() {
+                ErrorKind::UpstreamTimeout(_) => (),
+                _ => panic!("unexpected {:?}", e),
+            },
+            Ok(_) => panic!("read a stalled body"),
+        }
+    }
+
+    #[test]
+    fn reads_max_age_from_cache_control() {
+        assert_eq!(max_age_of("public, max-age=3600"), Some(Duration::from_secs(3600)));
+        assert_eq!(max_age_of("Max-Age=\"60\""), Some(Duration::from_secs(60)));
+        assert_eq!(max_age_of("no-store"), Some(Duration::from_secs(0)));
+        assert_eq!(max_age_of("private, no-cache, max-age=60"), Some(Duration::from_secs(0)));
+        assert_eq!(max_age_of("public"), None);
+        assert_eq!(max_age_of("max-age=soon"), None);
+    }
+
+    #[test]
+    fn static_search_compares_films_keys() {
+        let provider = StaticProvider::new(String::from("test"), vec![json!({"name": "Großstadt", "films": []})
+            , json!({"name": "Ｈｏｔｈ", "films": []}), json!({"name": "Endor", "films": []})]);
+        let names = |search: &str| -> Vec<String> {
+            let body : Value = serde_json::from_slice(&provider.search(search).wait().unwrap().body).unwrap();
+            body["results"].as_array().unwrap().iter().map(|p| String::from(p["name"].as_str().unwrap())).collect()
+        };
+        assert_eq!(names("GROSS"), vec!["Großstadt"]);
+        assert_eq!(names(" hoth"), vec!["Ｈｏｔｈ"]);
+        assert!(names("Tatooine").is_empty());
+    }
+
+    #[test]
+    fn follows_pages_below_the_base_url_only() {
+        let base = "https://swapi.dev/api";
+        assert!(within(base, "https://swapi.dev/api/planets/?search=a&page=2"));
+        assert!(within(base, "https://swapi.dev/api?page=2"));
+        assert!(!within(base, "https://swapi.dev/apix/planets/"));
+        assert!(!within(base, "https://swapi.dev.evil.com/api/planets/"));
+        assert!(!within(base, "http://169.254.169.254/latest/meta-data/"));
+    }
+}
@@ -0,0 +1,392 @@
+use actix::Arbiter;
+use errors::{Error, ErrorKind};
+use futures::{Future, future::{self, Either, Loop}};
+use rand::{self, Rng};
+use serde_json::value::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Timeout;
+use super::{FilmsProvider, Response};
+
+/// First delay between retries of a failed upstream request, doubled on every
+/// retry and spread by a random jitter up to its own length.
+const BACKOFF_MS: u64 = 250;
+
+/// Timeout, retries and circuit breaker settings of upstream requests.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub timeout: Duration,
+    pub retries: u32,
+    /// Consecutive failures opening the breaker.
+    pub threshold: u32,
+    /// Time an open breaker rejects requests before letting one probe through.
+    pub cooldown: Duration,
+    /// Longest a whole films lookup may take, every search page and retry
+    /// included, what readers wait at most for a films count.
+    pub lookup: Duration,
+}
+
+impl Policy {
+    fn backoff(&self, retry: u32) -> Duration {
+        let ms = BACKOFF_MS << retry.min(10);
+        Duration::from_millis(ms + rand::thread_rng().gen_range(0, ms + 1))
+    }
+
+    /// Longest a single request may take with every retry timing out.
+    pub fn deadline(&self) -> Duration {
+        let backoffs : u64 = (0..self.retries).map(|r| 2 * (BACKOFF_MS << r.min(10))).sum();
+        self.timeout * (self.retries + 1) + Duration::from_millis(backoffs)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Counters {
+    requests: u64,
+    failures: u64,
+    retries: u64,
+    rejected: u64,
+    opened: u64,
+}
+
+struct Circuit {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    counters: Counters,
+}
+
+/// Circuit breaker of the upstream api, opened after `threshold` consecutive
+/// failures so lookups fail fast instead of piling up on a dead upstream.
+pub struct Breaker {
+    policy: Policy,
+    circuit: Mutex<Circuit>,
+}
+
+impl Breaker {
+    pub fn new(policy: Policy) -> Breaker {
+        Breaker{policy: policy, circuit: Mutex::new(Circuit{state: BreakerState::Closed, failures: 0
+            , opened_at: None, counters: Counters::default()})}
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Whether a request may go upstream, an open breaker lets a single probe
+    /// through once cooled down.
+    fn allow(&self) -> Admission {
+        let mut c = self.circuit.lock().unwrap_or_else(|p| p.into_inner());
+        c.counters.requests += 1;
+        let cooled = c.opened_at.map_or(true, |t| t.elapsed() >= self.policy.cooldown);
+        match c.state {
+            BreakerState::Closed => Admission::Request,
+            BreakerState::Open if cooled => {
+                info!("upstream circuit breaker half open, probing");
+                c.state = BreakerState::HalfOpen;
+                Admission::Probe
+            }
+            _ => {
+                c.counters.rejected += 1;
+                Admission::Rejected
+            }
+        }
+    }
+
+    fn success(&self) {
+        let mut c = self.circuit.lock().unwrap_or_else(|p| p.into_inner());
+        if c.state != BreakerState::Closed {
+            info!("upstream circuit breaker closed");
+        }
+        c.state = BreakerState::Closed;
+        c.failures = 0;
+        c.opened_at = None;
+    }
+
+    fn failure(&self) {
+        let mut c = self.circuit.lock().unwrap_or_else(|p| p.into_inner());
+        c.counters.failures += 1;
+        c.failures += 1;
+        if c.state == BreakerState::HalfOpen || (c.state == BreakerState::Closed && c.failures >= self.policy.threshold) {
+            warn!("upstream circuit breaker open after {} failures, rejecting for {}s", c.failures
+                , self.policy.cooldown.as_secs());
+            c.state = BreakerState::Open;
+            c.opened_at = Some(Instant::now());
+            c.counters.opened += 1;
+        }
+    }
+
+    fn retried(&self) {
+        let mut c = self.circuit.lock().unwrap_or_else(|p| p.into_inner());
+        c.counters.retries += 1;
+    }
+
+    /// Breaker state, settings and counters reported on `/admin/upstream`.
+    pub fn stats(&self) -> Value {
+        let c = self.circuit.lock().unwrap_or_else(|p| p.into_inner());
+        json!({
+            "state": c.state,
+            "consecutive_failures": c.failures,
+            "open_for": open_for(&c, self.policy.cooldown),
+            "counters": c.counters,
+            "policy": {
+                "timeout": self.policy.timeout.as_secs(),
+                "retries": self.policy.retries,
+                "threshold": self.policy.threshold,
+                "cooldown": self.policy.cooldown.as_secs(),
+                "lookup": self.policy.lookup.as_secs(),
+            },
+        })
+    }
+}
+
+enum Admission {
+    Request,
+    /// The single request let through a half open breaker.
+    Probe,
+    Rejected,
+}
+
+/// Request let through the breaker, reporting its outcome once. A probe
+/// dropped unresolved, say by a lookup timeout, counts as failed so the
+/// breaker opens again instead of staying half open for good.
+struct Attempt {
+    breaker: Arc<Breaker>,
+    probe: bool,
+    done: bool,
+}
+
+impl Attempt {
+    fn new(breaker: Arc<Breaker>, probe: bool) -> Attempt {
+        Attempt{breaker: breaker, probe: probe, done: false}
+    }
+
+    fn success(mut self) {
+        self.done = true;
+        self.breaker.success();
+    }
+
+    fn failure(mut self) {
+        self.done = true;
+        self.breaker.failure();
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        if self.probe && !self.done {
+            warn!("upstream probe dropped unresolved");
+            self.breaker.failure();
+        }
+    }
+}
+
+/// Seconds an open breaker still rejects requests.
+fn open_for(c: &Circuit, cooldown: Duration) -> Option<u64> {
+    match (c.state, c.opened_at) {
+        (BreakerState::Open, Some(t)) if t.elapsed() < cooldown => Some((cooldown - t.elapsed()).as_secs()),
+        _ => None,
+    }
+}
+
+fn retryable(e: &Error) -> bool {
+    match *e.kind() {
+        ErrorKind::Upstream(_) | ErrorKind::UpstreamTimeout(_) => true,
+        _ => false,
+    }
+}
+
+/// Provider retrying failed requests with backoff and jitter behind a
+/// circuit breaker.
+pub struct ResilientProvider {
+    inner: Arc<FilmsProvider>,
+    breaker: Arc<Breaker>,
+}
+
+impl ResilientProvider {
+    pub fn new(inner: Arc<FilmsProvider>, breaker: Arc<Breaker>) -> ResilientProvider {
+        ResilientProvider{inner: inner, breaker: breaker}
+    }
+
+    fn call<F>(&self, request: F) -> Box<Future<Item=Response, Error=Error>> where
+        F: Fn(&FilmsProvider) -> Box<Future<Item=Response, Error=Error>> + 'static
+    {
+        let inner = self.inner.clone();
+        let breaker = self.breaker.clone();
+        Box::new(future::loop_fn(0, move |retry| {
+            let attempt = match breaker.allow() {
+                Admission::Rejected => return Either::A(future::err(Error::from_kind(ErrorKind::Upstream(
+                    String::from("upstream circuit breaker open"))))),
+                Admission::Request => Attempt::new(breaker.clone(), false),
+                Admission::Probe => Attempt::new(breaker.clone(), true),
+            };
+            let breaker = breaker.clone();
+            Either::B(request(&*inner).then(move |res| match res {
+                Ok(response) => {
+                    attempt.success();
+                    Either::A(future::ok(Loop::Break(response)))
+                }
+                Err(e) => {
+                    attempt.failure();
+                    if !retryable(&e) || retry >= breaker.policy().retries {
+                        return Either::A(future::err(e));
+                    }
+                    breaker.retried();
+                    let delay = breaker.policy().backoff(retry);
+                    debug!("retrying upstream request in {:?}: \t {}", delay, e);
+                    match Timeout::new(delay, Arbiter::handle()) {
+                        Ok(timeout) => Either::B(timeout.map_err(Error::from).map(move |_| Loop::Continue(retry + 1))),
+                        Err(io) => Either::A(future::err(Error::from(io))),
+                    }
+                }
+            }))
+        }))
+    }
+}
+
+impl FilmsProvider for ResilientProvider {
+    fn source(&self) -> String {
+        self.inner.source()
+    }
+
+    fn search(&self, name: &str) -> Box<Future<Item=Response, Error=Error>> {
+        let name = String::from(name);
+        self.call(move |p| p.search(&name))
+    }
+
+    fn next(&self, url: &str) -> Box<Future<Item=Response, Error=Error>> {
+        let url = String::from(url);
+        self.call(move |p| p.next(&url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use std::thread;
+    use stub;
+    use upstream::HttpProvider;
+
+    fn policy(retries: u32, threshold: u32) -> Policy {
+        Policy{timeout: Duration::from_secs(2), retries: retries, threshold: threshold
+            , cooldown: Duration::from_millis(100), lookup: Duration::from_secs(10)}
+    }
+
+    /// Http provider recording the breaker state each request went through in.
+    struct Observed {
+        inner: HttpProvider,
+        breaker: Arc<Breaker>,
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FilmsProvider for Observed {
+        fn source(&self) -> String {
+            self.inner.source()
+        }
+
+        fn search(&self, name: &str) -> Box<Future<Item=Response, Error=Error>> {
+            let state = self.breaker.stats()["state"].as_str().map(String::from).unwrap_or_default();
+            self.seen.lock().unwrap().push(state);
+            self.inner.search(name)
+        }
+    }
+
+    fn observed(url: &str, policy: Policy) -> (ResilientProvider, Arc<Breaker>, Arc<Mutex<Vec<String>>>) {
+        let breaker = Arc::new(Breaker::new(policy));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let inner = Observed{inner: HttpProvider::new(url, policy.timeout), breaker: breaker.clone(), seen: seen.clone()};
+        (ResilientProvider::new(Arc::new(inner), breaker.clone()), breaker, seen)
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        let policy = policy(3, 5);
+        for retry in 0..12 {
+            let ms = BACKOFF_MS << retry.min(10);
+            let delay = policy.backoff(retry);
+            assert!(delay >= Duration::from_millis(ms) && delay <= Duration::from_millis(2 * ms), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn deadline_covers_every_retry_of_a_request() {
+        // 3 attempts of 2s, backoffs of at most 500ms, 1s and 2s
+        assert_eq!(policy(3, 5).deadline(), Duration::from_millis(6000 + 500 + 1000 + 2000));
+        assert_eq!(policy(0, 5).deadline(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn retries_failed_requests_until_one_succeeds() {
+        let server = stub::serve(vec![stub::Reply::status(500), stub::Reply::status(503)
+            , stub::Reply::json(r#"{"next":null,"results":[]}"#).header("Cache-Control", "max-age=60")]);
+        let (provider, breaker, _) = observed(&server.url, policy(2, 5));
+
+        let response = System::new("upstream").run_until_complete(provider.search("Hoth")).unwrap();
+        assert_eq!(response.body, br#"{"next":null,"results":[]}"#.to_vec());
+        assert_eq!(response.max_age, Some(Duration::from_secs(60)));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| r.method == "GET" && r.path == "/planets/?search=Hoth"));
+
+        let stats = breaker.stats();
+        assert_eq!(stats["state"], "closed");
+        assert_eq!(stats["counters"], json!({"requests": 3, "failures": 2, "retries": 2, "rejected": 0, "opened": 0}));
+    }
+
+    #[test]
+    fn breaker_opens_then_probes_then_closes() {
+        let server = stub::serve(vec![stub::Reply::status(500), stub::Reply::status(500)
+            , stub::Reply::json(r#"{"next":null,"results":[]}"#)]);
+        let (provider, breaker, seen) = observed(&server.url, policy(0, 2));
+        let mut sys = System::new("upstream");
+
+        assert!(sys.run_until_complete(provider.search("Hoth")).is_err());
+        assert_eq!(breaker.stats()["state"], "closed");
+        assert!(sys.run_until_complete(provider.search("Hoth")).is_err());
+        assert_eq!(breaker.stats()["state"], "open");
+
+        // rejected without going upstream
+        let rejected = sys.run_until_complete(provider.search("Hoth")).err().unwrap();
+        assert_eq!(rejected.kind().message(), "upstream circuit breaker open");
+        assert_eq!(server.requests().len(), 2);
+
+        thread::sleep(Duration::from_millis(150));
+        assert!(sys.run_until_complete(provider.search("Hoth")).is_ok());
+        assert_eq!(*seen.lock().unwrap(), vec!["closed", "closed", "half_open"]);
+
+        let stats = breaker.stats();
+        assert_eq!(stats["state"], "closed");
+        assert_eq!(stats["counters"], json!({"requests": 4, "failures": 2, "retries": 0, "rejected": 1, "opened": 1}));
+    }
+
+    #[test]
+    fn recovers_from_a_dropped_probe() {
+        let server = stub::serve(vec![stub::Reply::status(500), stub::Reply::json(r#"{"next":null,"results":[]}"#)]);
+        let (provider, breaker, seen) = observed(&server.url, policy(0, 1));
+        let mut sys = System::new("upstream");
+
+        assert!(sys.run_until_complete(provider.search("Hoth")).is_err());
+        assert_eq!(breaker.stats()["state"], "open");
+
+        // the probe is let through as the lookup starts, then given up on
+        thread::sleep(Duration::from_millis(150));
+        let probe = provider.search("Hoth");
+        assert_eq!(breaker.stats()["state"], "half_open");
+        drop(probe);
+        assert_eq!(breaker.stats()["state"], "open");
+
+        thread::sleep(Duration::from_millis(150));
+        assert!(sys.run_until_complete(provider.search("Hoth")).is_ok());
+        assert_eq!(breaker.stats()["state"], "closed");
+        assert_eq!(*seen.lock().unwrap(), vec!["closed", "half_open", "half_open"]);
+        assert_eq!(breaker.stats()["counters"]["opened"], 2);
+    }
+}